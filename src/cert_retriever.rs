@@ -1,38 +1,124 @@
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::client::WebPkiServerVerifier;
 use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::sign::CertifiedKey;
 use rustls::{ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
-use std::fmt::Debug;
-use std::io::{Write};
-use std::net::TcpStream;
+use crate::client_auth::{ClientAuth, ClientAuthRecorder};
+use crate::config::Site;
+use crate::hostname::ReferenceIdentity;
+use crate::lint;
+use crate::lint::LintFinding;
+use crate::profile;
+use crate::profile::PublicKeyInfo;
+use crate::proxy::{Proxy, ProxySetting, TunnelError};
+use crate::starttls::{NegotiationError, StartTls};
+use crate::validation::{check_chain_order, validate_chain, ChainOrder, Validation};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 use time::OffsetDateTime;
 use x509_parser::extensions::GeneralName;
 use x509_parser::prelude::{FromDer, X509Certificate};
 
+/// Upper bounds of the phases of the connection to an endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    /// The whole TLS handshake
+    pub handshake: Duration,
+    /// Each single read from and write to the socket
+    pub read: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(10),
+            handshake: Duration::from_secs(10),
+            read: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Server name sent in the ClientHello
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Sni {
+    /// The target name, unless it is an IP address
+    #[default]
+    Target,
+    /// Another name than the target, which is also the name the certificate is matched against
+    Name(String),
+    /// No server name, to get the default certificate of the server
+    None,
+}
+
+/// How to reach an endpoint and get it to the TLS handshake
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectOptions {
+    pub timeouts: Timeouts,
+    /// Plaintext protocol to upgrade the connection with, implicit TLS if None
+    pub starttls: Option<StartTls>,
+    /// Proxy to tunnel the connection through, direct connection if None
+    pub proxy: Option<Proxy>,
+    /// Address to connect to instead of the first reachable one the target resolves to
+    pub address: Option<IpAddr>,
+    pub sni: Sni,
+}
+
+impl ConnectOptions {
+    /// Options of `site`, fails if the proxy from the environment is invalid
+    pub fn for_site(site: &Site) -> Result<ConnectOptions, CertError> {
+        let proxy = match &site.connection.proxy {
+            Some(ProxySetting::Direct) => None,
+            Some(ProxySetting::Via(proxy)) => Some(proxy.clone()),
+            None => Proxy::from_environment(&site.target_fqn).map_err(CertError::ProxyFailed)?,
+        };
+        Ok(ConnectOptions {
+            timeouts: site.connection.timeouts(),
+            starttls: site.starttls.or_else(|| StartTls::for_service(&site.service)),
+            proxy,
+            address: site.connect_address,
+            sni: match site.sni.as_deref() {
+                None => Sni::Target,
+                Some("") => Sni::None,
+                Some(name) => Sni::Name(name.to_owned()),
+            },
+        })
+    }
+}
 
 pub struct CertRetriever {
-    config : Arc<ClientConfig>
+    config : Arc<ClientConfig>,
+    /// Same as `config`, but without server name indication
+    config_without_sni : Arc<ClientConfig>,
+    verifier : Arc<WebPkiServerVerifier>,
+    /// Presented if the server requests client authentication
+    client_key : Option<Arc<CertifiedKey>>
 }
+
+/// Accepts every certificate during the handshake so that also invalid certificates can be
+/// captured. The chain is validated separately once the handshake is done.
 #[derive(Debug)]
 struct NullVerifier {
     parent : Arc<WebPkiServerVerifier>
 }
 
 impl NullVerifier {
-    pub fn new() -> NullVerifier {
-        let root_store = RootCertStore {
-            roots: webpki_roots::TLS_SERVER_ROOTS.into(),
-        };
+    pub fn new(parent : Arc<WebPkiServerVerifier>) -> NullVerifier {
         NullVerifier {
-            parent : WebPkiServerVerifier::builder(<Arc<RootCertStore>>::from(root_store)).allow_unknown_revocation_status().build().unwrap()
+            parent
         }
     }
 
 }
 impl ServerCertVerifier for NullVerifier {
     fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, Error> {
-        //self.parent.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
         Ok(ServerCertVerified::assertion())
     }
 
@@ -51,63 +137,405 @@ impl ServerCertVerifier for NullVerifier {
 
 impl CertRetriever {
     pub fn new() -> CertRetriever {
-        let root_store = RootCertStore {
+        CertRetriever::with_roots(RootCertStore {
             roots: webpki_roots::TLS_SERVER_ROOTS.into(),
-        };
+        })
+    }
+
+    /// Retriever which validates the presented chains against `root_store` instead of the webpki roots
+    pub fn with_roots(root_store: RootCertStore) -> CertRetriever {
+        CertRetriever::with_client_certificate(root_store, None)
+    }
+
+    /// Retriever which validates against `root_store` and presents `client_key` to servers requesting
+    /// client authentication
+    pub fn with_client_certificate(root_store: RootCertStore, client_key: Option<Arc<CertifiedKey>>) -> CertRetriever {
         let mut config = ClientConfig::builder()
-            .with_root_certificates(root_store)
+            .with_root_certificates(root_store.clone())
             .with_no_client_auth();
         config.enable_sni = true;
         config.enable_early_data = true;        
         config.key_log = Arc::new(rustls::KeyLogFile::new());
-        
-        //TODO: future approach to also include expired certificates
 
-        /* rustls::client::ServerCertVerifierBuilder */
-        let v  = <Box<dyn ServerCertVerifier>>::from(Box::new(NullVerifier::new()));
+        let verifier = WebPkiServerVerifier::builder(<Arc<RootCertStore>>::from(root_store)).allow_unknown_revocation_status().build().unwrap();
+        let v  = <Box<dyn ServerCertVerifier>>::from(Box::new(NullVerifier::new(verifier.clone())));
         config.dangerous().set_certificate_verifier( <Arc<dyn ServerCertVerifier>>::from(v));
+        let mut config_without_sni = config.clone();
+        config_without_sni.enable_sni = false;
         CertRetriever {
-            config : Arc::new(config)
+            config : Arc::new(config),
+            config_without_sni : Arc::new(config_without_sni),
+            verifier,
+            client_key
+        }
+    }
+
+    /// Retrieves the certificate of `site`, retrying transient failures with exponential backoff.
+    /// Returns the outcome of the last attempt together with the number of attempts made.
+    pub fn get_site_cert(&self, site: &Site) -> (Result<EndpointCertificate, CertError>, u32) {
+        let options = match ConnectOptions::for_site(site) {
+            Ok(options) => options,
+            Err(e) => return (Err(e), 1),
+        };
+        with_retries(site, || self.get_target_cert_from_endpoint(&site.target_fqn, site.port, &options))
+    }
+
+    /// Retrieves the certificate from every address the target of `site` resolves to, each with the
+    /// same server name. Returns a single outcome if the site has a `connect_address`, and a single outcome
+    /// without address if the resolution fails or the site is reached through a proxy, which resolves the
+    /// target itself.
+    pub fn get_site_cert_per_address(&self, site: &Site) -> Vec<(Option<IpAddr>, Result<EndpointCertificate, CertError>, u32)> {
+        let options = match ConnectOptions::for_site(site) {
+            Ok(options) => options,
+            Err(e) => return vec![(None, Err(e), 1)],
+        };
+        if options.proxy.is_some() || options.address.is_some() {
+            let (result, attempts) = with_retries(site, || self.get_target_cert_from_endpoint(&site.target_fqn, site.port, &options));
+            return vec![(options.address, result, attempts)];
         }
+        let addresses = match with_retries(site, || resolve(unbracketed(&site.target_fqn))) {
+            (Ok(addresses), _) => addresses,
+            (Err(e), attempts) => return vec![(None, Err(e), attempts)],
+        };
+        addresses.into_iter().map(|address| {
+            let options = ConnectOptions { address: Some(address), ..options.clone() };
+            let (result, attempts) = with_retries(site, || self.get_target_cert_from_endpoint(&site.target_fqn, site.port, &options));
+            (Some(address), result, attempts)
+        }).collect()
     }
 
-    pub fn get_target_cert_from_endpoint(&self, target_name: &str, target_port: u32) -> Result<SimpleCertificate, CertError> {
+    pub fn get_target_cert_from_endpoint(&self, target_name: &str, target_port: u32, options: &ConnectOptions) -> Result<EndpointCertificate, CertError> {
+        let timeouts = &options.timeouts;
         let full_target = format!("{}:{}", target_name, target_port);
-        let server_name : ServerName = String::from(target_name).try_into().unwrap();
-
-
-        match TcpStream::connect(&full_target) {
-            Ok(sock) => {
-                let mut sock = sock;
-                let mut conn = rustls::ClientConnection::new(self.config.clone(), server_name).unwrap();
-                
-                let mut tls = rustls::Stream::new(&mut conn, &mut sock);
-                if let Err(_e) = tls.write(b"\n") {
-                    //TODO change to proper debug logging
-                    //println!("Error during connect to {}: {}", full_target, e.kind().to_string());
-                }
+        let host = unbracketed(target_name);
+        // the name the certificate has to be valid for
+        let peer_name = match &options.sni {
+            Sni::Name(name) => name.as_str(),
+            Sni::Target | Sni::None => host,
+        };
+        let server_name = ServerName::try_from(peer_name.to_owned())
+            .map_err(|e| CertError::InvalidServerName(format!("{} is not a valid server name: {}", peer_name, e)))?;
+        let port = u16::try_from(target_port)
+            .map_err(|_| CertError::InvalidServerName(format!("{} is not a valid port", target_port)))?;
 
-                match SimpleCertificate::find_matching_certificate(target_name, tls.conn.peer_certificates()){
-                    None => {
-                        Err(CertError::TargetHasNoCertMatch(target_name.to_owned()))
-                    }
-                    Some(peer_cert) => {
-                        Ok(peer_cert.clone())
-                    }
-                }
+        let mut sock = match (&options.proxy, options.address) {
+            (Some(proxy), Some(address)) => connect_via_proxy(proxy, &address.to_string(), port, &full_target, timeouts)?,
+            (Some(proxy), None) => connect_via_proxy(proxy, host, port, &full_target, timeouts)?,
+            (None, address) => connect(host, address, port, &full_target, timeouts.connect)?,
+        };
+        if let Some(starttls) = options.starttls {
+            negotiate_starttls(&mut sock, starttls, peer_name, &full_target, timeouts)?;
+        }
+        let mut config = match options.sni {
+            Sni::None => ClientConfig::clone(&self.config_without_sni),
+            Sni::Target | Sni::Name(_) => ClientConfig::clone(&self.config),
+        };
+        let recorder = Arc::new(ClientAuthRecorder::new(self.client_key.clone()));
+        config.client_auth_cert_resolver = recorder.clone();
+        let mut conn = rustls::ClientConnection::new(Arc::new(config), server_name.clone())
+            .map_err(|e| CertError::HandshakeFailed(format!("Unable to start the handshake with {}: {}", full_target, e)))?;
+        let deadline = Instant::now() + timeouts.handshake;
+        let handshake_timeout = || CertError::HandshakeTimeout(
+            format!("Target {} did not complete the handshake within {} seconds", full_target, timeouts.handshake.as_secs()));
+        while conn.is_handshaking() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(handshake_timeout());
+            }
+            let io_timeout = Some(remaining.min(timeouts.read));
+            sock.set_read_timeout(io_timeout)
+                .and_then(|_| sock.set_write_timeout(io_timeout))
+                .map_err(|e| CertError::TargetNotReachable(format!("Target {} is unreachable: {}", full_target, e)))?;
+            if let Err(e) = conn.complete_io(&mut sock) {
+                return Err(match e.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut if Instant::now() >= deadline => handshake_timeout(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => CertError::HandshakeTimeout(
+                        format!("Target {} sent no data for {} seconds", full_target, timeouts.read.as_secs())),
+                    _ => handshake_error(&full_target, e, &recorder.client_auth()),
+                });
+            }
+        }
+
+        let chain = match conn.peer_certificates() {
+            Some(chain) if !chain.is_empty() => chain,
+            _ => return Err(CertError::EmptyPeerChain(format!("Target {} presented no certificate", full_target))),
+        };
+        let matching = SimpleCertificate::find_matching_certificate(peer_name, Some(chain)).or_else(|| {
+            // a certificate which does not match is still of interest, e.g. the default certificate of the
+            // server, the validation reports the mismatch
+            SimpleCertificate::from_certificate_der(&chain[0]).ok()
+        });
+        match matching {
+            None => {
+                Err(CertError::TargetHasNoCertMatch(peer_name.to_owned()))
             }
-            Err(_) => {
-                Err(CertError::TargetNotReachable(format!("Target {} is unreachable", &full_target).to_string()))
+            Some(peer_cert) => {
+                Ok(EndpointCertificate {
+                    certificate : peer_cert,
+                    chain : chain.iter().filter_map(|cert| SimpleCertificate::from_certificate_der(cert).ok()).collect(),
+                    chain_order : check_chain_order(chain),
+                    validation : Some(validate_chain(&self.verifier, chain, &server_name, UnixTime::now())),
+                    client_auth : recorder.client_auth()
+                })
             }
         }
     }
 }
 
-#[derive(Debug)]
+/// Runs `attempt` until it succeeds, fails permanently or the retries of `site` are used up, sleeping
+/// with exponential backoff in between. Returns the last outcome together with the number of attempts made.
+fn with_retries<T, F>(site: &Site, mut attempt: F) -> (Result<T, CertError>, u32) where F: FnMut() -> Result<T, CertError> {
+    let mut attempts = 1;
+    loop {
+        let result = attempt();
+        match &result {
+            Err(e) if e.is_transient() && attempts <= site.connection.retries() => {
+                thread::sleep(site.connection.retry_backoff(attempts));
+                attempts += 1;
+            }
+            _ => return (result, attempts),
+        }
+    }
+}
+
+/// IPv6 addresses may be given in brackets as in URLs
+fn unbracketed(target_name: &str) -> &str {
+    target_name.strip_prefix('[').and_then(|t| t.strip_suffix(']')).unwrap_or(target_name)
+}
+
+/// All addresses of `host` without duplicates, in the order returned by the resolver
+fn resolve(host: &str) -> Result<Vec<IpAddr>, CertError> {
+    let mut addresses: Vec<IpAddr> = Vec::new();
+    for address in (host, 0).to_socket_addrs()
+        .map_err(|e| CertError::DnsResolutionFailed(format!("Unable to resolve {}: {}", host, e)))? {
+        if !addresses.contains(&address.ip()) {
+            addresses.push(address.ip());
+        }
+    }
+    if addresses.is_empty() {
+        return Err(CertError::DnsResolutionFailed(format!("No address found for {}", host)));
+    }
+    Ok(addresses)
+}
+
+/// Connects to `address` if given, otherwise resolves `host` and connects to the first address accepting the connection
+fn connect(host: &str, address: Option<IpAddr>, port: u16, full_target: &str, timeout: Duration) -> Result<TcpStream, CertError> {
+    let addresses = match address {
+        Some(address) => vec![address],
+        None => resolve(host)?,
+    };
+    let mut last_error = CertError::DnsResolutionFailed(format!("No address found for {}", host));
+    for address in addresses {
+        match TcpStream::connect_timeout(&SocketAddr::new(address, port), timeout) {
+            Ok(sock) => return Ok(sock),
+            Err(e) => last_error = match e.kind() {
+                ErrorKind::ConnectionRefused => CertError::ConnectionRefused(format!("Target {} refused the connection", full_target)),
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => CertError::ConnectTimeout(
+                    format!("Target {} did not accept the connection within {} seconds", full_target, timeout.as_secs())),
+                _ => CertError::TargetNotReachable(format!("Target {} is unreachable: {}", full_target, e)),
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Connects to `proxy` and lets it open a tunnel to the target, which the proxy resolves itself
+fn connect_via_proxy(proxy: &Proxy, host: &str, port: u16, full_target: &str, timeouts: &Timeouts) -> Result<TcpStream, CertError> {
+    let proxy_target = format!("{}:{}", proxy.host(), proxy.port());
+    let mut sock = connect(proxy.host(), None, proxy.port(), &proxy_target, timeouts.connect)
+        .map_err(|e| CertError::ProxyFailed(format!("Unable to connect to the proxy {}: {}", proxy, e.message())))?;
+    let io_timeout = timeouts.handshake.min(timeouts.read);
+    sock.set_read_timeout(Some(io_timeout))
+        .and_then(|_| sock.set_write_timeout(Some(io_timeout)))
+        .map_err(|e| CertError::ProxyFailed(format!("Unable to use the connection to the proxy {}: {}", proxy, e)))?;
+    proxy.tunnel(&mut sock, host, port).map_err(|e| match e {
+        TunnelError::Proxy(message) => CertError::ProxyFailed(
+            format!("Proxy {} did not connect to {}: {}", proxy, full_target, message)),
+        TunnelError::Refused(message) => CertError::ConnectionRefused(
+            format!("Target {} refused the connection via proxy {}: {}", full_target, proxy, message)),
+        TunnelError::Unreachable(message) => CertError::TargetNotReachable(
+            format!("Target {} is unreachable via proxy {}: {}", full_target, proxy, message)),
+        TunnelError::Io(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => CertError::ProxyFailed(
+            format!("Proxy {} did not answer within {} seconds", proxy, io_timeout.as_secs())),
+        TunnelError::Io(e) => CertError::ProxyFailed(format!("Proxy {} did not connect to {}: {}", proxy, full_target, e)),
+    })?;
+    Ok(sock)
+}
+
+/// Upgrades the plaintext connection, bounded by the handshake timeout as a whole and the read timeout per read
+fn negotiate_starttls(sock: &mut TcpStream, starttls: StartTls, host: &str, full_target: &str, timeouts: &Timeouts) -> Result<(), CertError> {
+    let io_timeout = timeouts.handshake.min(timeouts.read);
+    sock.set_read_timeout(Some(io_timeout))
+        .and_then(|_| sock.set_write_timeout(Some(io_timeout)))
+        .map_err(|e| CertError::TargetNotReachable(format!("Target {} is unreachable: {}", full_target, e)))?;
+    starttls.negotiate(sock, host).map_err(|e| match e {
+        NegotiationError::NotOffered(message) => CertError::StartTlsNotOffered(format!("Target {}: {}", full_target, message)),
+        NegotiationError::Protocol(message) => CertError::StartTlsFailed(
+            format!("{} negotiation with {} failed: {}", starttls.label(), full_target, message)),
+        NegotiationError::Io(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => CertError::HandshakeTimeout(
+            format!("Target {} did not answer the {} negotiation within {} seconds", full_target, starttls.label(), io_timeout.as_secs())),
+        NegotiationError::Io(e) => CertError::StartTlsFailed(
+            format!("{} negotiation with {} failed: {}", starttls.label(), full_target, e)),
+    })
+}
+
+/// Maps an error of the handshake, which rustls reports wrapped in an [std::io::Error]
+fn handshake_error(full_target: &str, e: std::io::Error, client_auth: &ClientAuth) -> CertError {
+    match e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()) {
+        Some(Error::AlertReceived(alert)) => CertError::HandshakeAlert {
+            code: u8::from(*alert),
+            message: if client_auth.requested && !client_auth.presented {
+                format!("Target {} aborted the handshake with alert {:?}, it requested a client certificate but none was presented",
+                        full_target, alert)
+            } else {
+                format!("Target {} aborted the handshake with alert {:?}", full_target, alert)
+            },
+        },
+        Some(e @ (Error::PeerIncompatible(_) | Error::InvalidMessage(_) | Error::InappropriateMessage { .. } |
+                  Error::InappropriateHandshakeMessage { .. })) => {
+            CertError::ProtocolMismatch(format!("Target {} does not speak a supported TLS version: {}", full_target, e))
+        }
+        Some(e) => CertError::HandshakeFailed(format!("Handshake with {} failed: {}", full_target, e)),
+        None => CertError::HandshakeFailed(format!("Handshake with {} failed: {}", full_target, e)),
+    }
+}
+
+/// Certificate retrieved from an endpoint together with the validation result of the presented chain
+#[derive(Clone)]
+pub struct EndpointCertificate {
+    pub certificate : SimpleCertificate,
+    /// All certificates in the order presented by the server, usually starting with `certificate`
+    pub chain : Vec<SimpleCertificate>,
+    pub chain_order : ChainOrder,
+    /// Absent for certificates read from files, which have no server name to validate against
+    pub validation : Option<Validation>,
+    /// Whether the server requested a client certificate
+    pub client_auth : ClientAuth
+}
+
+#[derive(Debug, Clone)]
 pub enum CertError {
     InvalidFormat(String),
+    /// The connection failed for another reason than the ones below, e.g. no route to the host
     TargetNotReachable(String),
-    TargetHasNoCertMatch(String)
+    TargetHasNoCertMatch(String),
+    /// The target is neither a valid DNS name nor an IP address
+    InvalidServerName(String),
+    DnsResolutionFailed(String),
+    ConnectionRefused(String),
+    ConnectTimeout(String),
+    /// The server aborted the handshake, `code` is the TLS alert description
+    HandshakeAlert { code: u8, message: String },
+    /// The server does not speak TLS or none of the versions and cipher suites we offer
+    ProtocolMismatch(String),
+    /// The server did not complete the handshake in time or stopped sending data
+    HandshakeTimeout(String),
+    /// The server does not advertise the upgrade to TLS or refused it
+    StartTlsNotOffered(String),
+    /// The plaintext negotiation before the handshake failed
+    StartTlsFailed(String),
+    /// The proxy is invalid, unreachable or refused to open the tunnel to the target
+    ProxyFailed(String),
+    /// Any other error during the handshake, e.g. the connection was closed
+    HandshakeFailed(String),
+    EmptyPeerChain(String),
+    /// The certificate file or directory of a `file` site can't be read
+    FileUnreadable(String),
+    /// A keystore can't be opened, e.g. due to a wrong or missing password
+    KeystoreFailed(String),
+    /// The check of the site failed unexpectedly
+    Internal(String),
+}
+
+impl CertError {
+    /// Name of the variant, as used in the structured outputs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CertError::InvalidFormat(_) => "InvalidFormat",
+            CertError::TargetNotReachable(_) => "TargetNotReachable",
+            CertError::TargetHasNoCertMatch(_) => "TargetHasNoCertMatch",
+            CertError::InvalidServerName(_) => "InvalidServerName",
+            CertError::DnsResolutionFailed(_) => "DnsResolutionFailed",
+            CertError::ConnectionRefused(_) => "ConnectionRefused",
+            CertError::ConnectTimeout(_) => "ConnectTimeout",
+            CertError::HandshakeAlert { .. } => "HandshakeAlert",
+            CertError::ProtocolMismatch(_) => "ProtocolMismatch",
+            CertError::HandshakeTimeout(_) => "HandshakeTimeout",
+            CertError::StartTlsNotOffered(_) => "StartTlsNotOffered",
+            CertError::StartTlsFailed(_) => "StartTlsFailed",
+            CertError::ProxyFailed(_) => "ProxyFailed",
+            CertError::HandshakeFailed(_) => "HandshakeFailed",
+            CertError::EmptyPeerChain(_) => "EmptyPeerChain",
+            CertError::FileUnreadable(_) => "FileUnreadable",
+            CertError::KeystoreFailed(_) => "KeystoreFailed",
+            CertError::Internal(_) => "Internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            CertError::InvalidFormat(e) |
+            CertError::TargetNotReachable(e) |
+            CertError::TargetHasNoCertMatch(e) |
+            CertError::InvalidServerName(e) |
+            CertError::DnsResolutionFailed(e) |
+            CertError::ConnectionRefused(e) |
+            CertError::ConnectTimeout(e) |
+            CertError::HandshakeAlert { message: e, .. } |
+            CertError::ProtocolMismatch(e) |
+            CertError::HandshakeTimeout(e) |
+            CertError::StartTlsNotOffered(e) |
+            CertError::StartTlsFailed(e) |
+            CertError::ProxyFailed(e) |
+            CertError::HandshakeFailed(e) |
+            CertError::EmptyPeerChain(e) |
+            CertError::FileUnreadable(e) |
+            CertError::KeystoreFailed(e) |
+            CertError::Internal(e) => e,
+        }
+    }
+
+    /// Whether another attempt may succeed, as opposed to failures caused by the config or the
+    /// certificate served
+    pub fn is_transient(&self) -> bool {
+        matches!(self,
+            CertError::TargetNotReachable(_) |
+            CertError::DnsResolutionFailed(_) |
+            CertError::ConnectionRefused(_) |
+            CertError::ConnectTimeout(_) |
+            CertError::HandshakeTimeout(_) |
+            CertError::HandshakeFailed(_))
+    }
+
+    /// TLS alert description sent by the server, if it aborted the handshake
+    pub fn alert_code(&self) -> Option<u8> {
+        match self {
+            CertError::HandshakeAlert { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+}
+
+impl Display for CertError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for CertError {}
+
+/// Serialized as `{"kind": .., "message": ..}` plus the `alertCode` of a [CertError::HandshakeAlert]
+impl Serialize for CertError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let alert_code = self.alert_code();
+        let mut state = serializer.serialize_struct("CertError", if alert_code.is_some() { 3 } else { 2 })?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", self.message())?;
+        if let Some(code) = alert_code {
+            state.serialize_field("alertCode", &code)?;
+        }
+        state.end()
+    }
 }
 
 
@@ -137,29 +565,88 @@ pub fn get_san_dns_names(cert : &X509Certificate) -> Vec<String> {
     dns_names
 }
 
-#[derive(Clone)]
+pub fn get_san_ip_addresses(cert : &X509Certificate) -> Vec<IpAddr> {
+    let mut ip_addresses = Vec::new();
+    if let Ok( Some(san)) = cert.subject_alternative_name() {
+        san.value.general_names.iter().for_each(| gn | {
+            if let GeneralName::IPAddress(bytes) = gn {
+                if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                    ip_addresses.push(IpAddr::from(octets));
+                } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                    ip_addresses.push(IpAddr::from(octets));
+                }
+            }
+        })
+    }
+    ip_addresses
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct SimpleCertificate{
     common_name : String,
+    /// Full distinguished names, e.g. `O=Example, CN=Example CA`
+    subject : String,
+    issuer : String,
     serial_number : String,
+    #[serde(with = "time::serde::rfc3339")]
+    not_before : OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
     expiration_date : OffsetDateTime,
+    #[serde(rename = "sanDnsNames")]
     san_list : Vec<String>,
+    san_ip_addresses : Vec<IpAddr>,
     is_ca : bool,
-    pem : String
+    signature_algorithm : String,
+    public_key : PublicKeyInfo,
+    key_usage : Vec<String>,
+    extended_key_usage : Vec<String>,
+    sha1_fingerprint : String,
+    sha256_fingerprint : String,
+    spki_sha256 : String,
+    subject_key_id : Option<String>,
+    authority_key_id : Option<String>,
+    ocsp_urls : Vec<String>,
+    ca_issuers_urls : Vec<String>,
+    crl_distribution_points : Vec<String>,
+    policy_oids : Vec<String>,
+    pem : String,
+    /// Findings of all lint rules, filtered by the disabled rules of the site before they are reported
+    #[serde(skip)]
+    lints : Vec<LintFinding>
 }
 
 
 impl SimpleCertificate {
 
-    fn internal_build(cert : &X509Certificate, pem : String) -> SimpleCertificate {
+    fn internal_build(cert : &X509Certificate, der : &[u8], pem : String) -> SimpleCertificate {
 
         //let cert = Arc::new(c.to_owned());
         SimpleCertificate {
             common_name : get_common_name(cert),
+            subject : cert.subject().to_string(),
+            issuer : cert.issuer().to_string(),
             serial_number : get_serial_number(cert),
+            not_before : cert.validity().not_before.to_datetime(),
             expiration_date : cert.validity().not_after.to_datetime(),
             san_list : get_san_dns_names(cert),
+            san_ip_addresses : get_san_ip_addresses(cert),
             is_ca : cert.is_ca(),
-            pem
+            signature_algorithm : profile::get_signature_algorithm(cert),
+            public_key : profile::get_public_key(cert),
+            key_usage : profile::get_key_usage(cert),
+            extended_key_usage : profile::get_extended_key_usage(cert),
+            sha1_fingerprint : profile::sha1_fingerprint(der),
+            sha256_fingerprint : profile::sha256_fingerprint(der),
+            spki_sha256 : profile::spki_sha256(cert),
+            subject_key_id : profile::get_subject_key_id(cert),
+            authority_key_id : profile::get_authority_key_id(cert),
+            ocsp_urls : profile::get_ocsp_urls(cert),
+            ca_issuers_urls : profile::get_ca_issuers_urls(cert),
+            crl_distribution_points : profile::get_crl_distribution_points(cert),
+            policy_oids : profile::get_policy_oids(cert),
+            pem,
+            lints : lint::lint(cert)
         }
     }
 
@@ -192,7 +679,7 @@ impl SimpleCertificate {
         let pem = Self::create_pem(der_cert.as_ref());
         match X509Certificate::from_der(der_cert.as_ref()) {
             Ok((_,cert)) => {
-                Ok(SimpleCertificate::internal_build(&cert, pem))
+                Ok(SimpleCertificate::internal_build(&cert, der_cert.as_ref(), pem))
             }
             Err(e) => {
                 Err(CertError::InvalidFormat(e.to_string()))
@@ -200,19 +687,16 @@ impl SimpleCertificate {
         }
     }
 
+    /// The first of `certs` which is valid for `peer_name` according to RFC 6125
     pub fn find_matching_certificate(peer_name : &str, certs : Option<&[CertificateDer]>) -> Option<SimpleCertificate>{
-        let mut found_matching_cert = None;
-        if let Some(cert) = certs {
-            cert.iter().for_each(|cert| {
-                if let Ok(c) = Self::from_certificate_der(cert) {
-                    if c.get_common_name() == peer_name ||
-                        c.get_san_dns_names().iter().any(|name| name == peer_name){
-                        found_matching_cert = Some(c);
-                    }
-                };
-            });
-        };
-        found_matching_cert
+        let reference = ReferenceIdentity::parse(peer_name).ok()?;
+        certs?.iter()
+            .filter_map(|cert| Self::from_certificate_der(cert).ok())
+            .find(|c| c.matches(&reference))
+    }
+
+    pub fn matches(&self, reference : &ReferenceIdentity) -> bool {
+        reference.matches(&self.san_list, &self.san_ip_addresses, &self.common_name)
     }
 
     pub fn get_serial_number(&self) -> &str {
@@ -227,10 +711,90 @@ impl SimpleCertificate {
         &self.san_list
     }
 
+    pub fn get_san_ip_addresses(&self) -> &[IpAddr] {
+        &self.san_ip_addresses
+    }
+
+    pub fn get_subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn get_issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    pub fn get_not_before(&self) -> OffsetDateTime {
+        self.not_before
+    }
+
+    pub fn get_expiration_date(&self) -> OffsetDateTime {
+        self.expiration_date
+    }
+
+    pub fn is_ca(&self) -> bool {
+        self.is_ca
+    }
+
+    pub fn get_signature_algorithm(&self) -> &str {
+        &self.signature_algorithm
+    }
+
+    pub fn get_public_key(&self) -> &PublicKeyInfo {
+        &self.public_key
+    }
+
+    pub fn get_key_usage(&self) -> &[String] {
+        &self.key_usage
+    }
+
+    pub fn get_extended_key_usage(&self) -> &[String] {
+        &self.extended_key_usage
+    }
+
+    pub fn get_sha1_fingerprint(&self) -> &str {
+        &self.sha1_fingerprint
+    }
+
+    pub fn get_sha256_fingerprint(&self) -> &str {
+        &self.sha256_fingerprint
+    }
+
+    pub fn get_spki_sha256(&self) -> &str {
+        &self.spki_sha256
+    }
+
+    pub fn get_subject_key_id(&self) -> Option<&str> {
+        self.subject_key_id.as_deref()
+    }
+
+    pub fn get_authority_key_id(&self) -> Option<&str> {
+        self.authority_key_id.as_deref()
+    }
+
+    pub fn get_ocsp_urls(&self) -> &[String] {
+        &self.ocsp_urls
+    }
+
+    pub fn get_ca_issuers_urls(&self) -> &[String] {
+        &self.ca_issuers_urls
+    }
+
+    pub fn get_crl_distribution_points(&self) -> &[String] {
+        &self.crl_distribution_points
+    }
+
+    pub fn get_policy_oids(&self) -> &[String] {
+        &self.policy_oids
+    }
+
     pub fn get_pem(&self) -> String {
         self.pem.clone()
     }
 
+    pub fn get_lints(&self) -> &[LintFinding] {
+        &self.lints
+    }
+
     pub fn get_remaining_days(&self) -> i64 {
         let now = OffsetDateTime::now_utc();
         let end = self.expiration_date;
@@ -243,13 +807,25 @@ impl SimpleCertificate {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::client_auth::load_client_certificate;
+    use crate::config::{ClientCertificateConfig, ConnectionSettings};
+    use crate::validation::ValidationStatus;
+    use rustls::pki_types::pem::PemObject;
     use rustls::pki_types::CertificateDer;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
     use time::macros::format_description;
 
 
     const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
     const GATEKEEPER_CERT: &[u8] = include_bytes!("../testdata/gatekeeper.tschirky.ch.crt");
     const WWW_CERT: &[u8] = include_bytes!("../testdata/www.tschirky.ch.crt");
+    const LOCALHOST_CERT: &[u8] = include_bytes!("../testdata/pki/localhost.crt");
+    const LEGACY_CERT: &[u8] = include_bytes!("../testdata/pki/legacy.crt");
+    const LOCALHOST_KEY: &[u8] = include_bytes!("../testdata/pki/localhost.key");
+    const INTERMEDIATE_CERT: &[u8] = include_bytes!("../testdata/pki/intermediate.crt");
+    const ROOT_CERT: &[u8] = include_bytes!("../testdata/pki/root.crt");
 
 
     fn test_day_offset() -> i64 {
@@ -271,7 +847,7 @@ mod tests {
             let failure = c.get_remaining_days() - 38 + test_day_offset();
             assert!( failure < 2 && failure > -2);
         } else {
-            assert!(false);
+            panic!("Unable to load certificate");
         }
     }
 
@@ -302,33 +878,88 @@ mod tests {
         assert!(cert.is_none());
     }
 
+    #[test]
+    fn test_find_matching_certificate() {
+        let certs = [
+            CertificateDer::from_pem_slice(LEGACY_CERT).unwrap(),
+            CertificateDer::from_pem_slice(LOCALHOST_CERT).unwrap(),
+            CertificateDer::from(WWW_CERT),
+        ];
+        let matching = |name: &str| SimpleCertificate::find_matching_certificate(name, Some(&certs))
+            .map(|cert| cert.get_common_name().to_owned());
+
+        assert_eq!(matching("LocalHost."), Some("localhost".to_owned()));
+        assert_eq!(matching("api.test.cert-monitor.local"), Some("localhost".to_owned()));
+        assert_eq!(matching("bücher.test.cert-monitor.local"), Some("localhost".to_owned()));
+        assert_eq!(matching("a.b.test.cert-monitor.local"), None);
+        assert_eq!(matching("test.cert-monitor.local"), None);
+        assert_eq!(matching("127.0.0.1"), Some("localhost".to_owned()));
+        assert_eq!(matching("[::1]"), Some("localhost".to_owned()));
+        assert_eq!(matching("127.0.0.2"), None);
+        assert_eq!(matching("TSCHIRKY.CH"), Some("www.tschirky.ch".to_owned()));
+        // the common name is only used without subject alternative names
+        assert_eq!(matching("legacy.test.cert-monitor.local"), Some("Legacy.Test.Cert-Monitor.local".to_owned()));
+        assert_eq!(matching("Legacy.Test.Cert-Monitor.local."), Some("Legacy.Test.Cert-Monitor.local".to_owned()));
+    }
+
+    #[test]
+    fn test_san_ip_addresses() {
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(LOCALHOST_CERT).unwrap()).unwrap();
+        let expected: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+        assert_eq!(cert.get_san_ip_addresses(), expected.as_slice());
+        assert_eq!(cert.get_san_dns_names(), &["localhost", "*.test.cert-monitor.local"]);
+    }
+
+    #[test]
+    fn test_certificate_profile() {
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(INTERMEDIATE_CERT).unwrap()).unwrap();
+        assert_eq!(cert.get_subject(), "O=Cert Monitor Test, CN=Cert Monitor Test Intermediate CA");
+        assert_eq!(cert.get_issuer(), "O=Cert Monitor Test, CN=Cert Monitor Test Root CA");
+        assert!(cert.get_not_before() < cert.get_expiration_date());
+        assert!(cert.is_ca());
+        assert_eq!(cert.get_signature_algorithm(), "ecdsa-with-SHA256");
+        assert_eq!(cert.get_public_key().to_string(), "EC P-256");
+        assert_eq!(cert.get_key_usage(), ["keyCertSign", "cRLSign"]);
+        assert_eq!(cert.get_subject_key_id(), Some("c1921a3da6ef604e7938215ef3d4f5b0f0f1a678"));
+        assert_eq!(cert.get_sha256_fingerprint().len(), 64);
+
+        let json = serde_json::to_value(&cert).unwrap();
+        assert_eq!(json["publicKey"]["curve"], "P-256");
+        assert_eq!(json["authorityKeyId"], cert.get_authority_key_id().unwrap());
+        assert!(json["notBefore"].is_string());
+    }
+
     #[test]
     fn test_cert_retriever() {
         let retr = CertRetriever::new();
-        let cert = retr.get_target_cert_from_endpoint("www.ibm.com", 443);
+        let cert = retr.get_target_cert_from_endpoint("www.ibm.com", 443, &ConnectOptions::default());
         assert!(cert.is_ok());
-        assert_eq!(cert.unwrap().get_common_name(), "www.ibm.com")
+        let cert = cert.unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "www.ibm.com");
+        assert!(cert.validation.unwrap().is_valid());
 
     }
 
     #[test]
     fn test_cert_retriever_unreachable_target_1() {
+        // a port which was free a moment ago
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
         let retr = CertRetriever::new();
-        let cert = retr.get_target_cert_from_endpoint("www.tschirky.ch", 998);
+        let cert = retr.get_target_cert_from_endpoint("127.0.0.1", port as u32, &ConnectOptions::default());
 
         match cert {
             Err(e) => {
                 match e {
-                    CertError::TargetNotReachable(e) => {
-                        assert_eq!(e, "Target www.tschirky.ch:998 is unreachable");
+                    CertError::ConnectionRefused(e) => {
+                        assert_eq!(e, format!("Target 127.0.0.1:{} refused the connection", port));
                     }
                     _ => {
-                        assert!(false);
+                        panic!("Unexpected CertError");
                     }
                 }
             },
             Ok(_) => {
-                assert!(false)
+                panic!("We expect CertError");
             }
         }
     }
@@ -336,13 +967,13 @@ mod tests {
     #[test]
     fn test_cert_retriever_unreachable_target_2() {
         let retr = CertRetriever::new();
-        let cert = retr.get_target_cert_from_endpoint("schludri.e3ag.ch", 443);
+        let cert = retr.get_target_cert_from_endpoint("schludri.invalid", 443, &ConnectOptions::default());
 
         match cert {
             Err(e) => {
                 match e {
-                    CertError::TargetNotReachable(e) => {
-                        assert_eq!(e, "Target schludri.e3ag.ch:443 is unreachable");
+                    CertError::DnsResolutionFailed(e) => {
+                        assert!(e.starts_with("Unable to resolve schludri.invalid"), "{}", e);
                     }
                     _ => {
                         panic!("Unexpected CertError");
@@ -356,6 +987,295 @@ mod tests {
     }
 
 
+    /// Accepts a single connection, answers with `response` and closes it
+    fn serve_once(response: &'static [u8]) -> u32 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut hello = [0u8; 512];
+            let _ = sock.read(&mut hello);
+            let _ = sock.write_all(response);
+        });
+        port as u32
+    }
+
+    /// Accepts a single connection, plays `preamble` (lines expected from the client, each followed by the
+    /// answer) and then completes a TLS handshake with the localhost certificate of the test PKI
+    fn serve_tls_once(greeting: &'static str, preamble: &'static [(&'static str, &'static str)]) -> u32 {
+        serve_tls_after(move |sock| {
+            sock.write_all(greeting.as_bytes()).unwrap();
+            let mut reader = std::io::BufReader::new(sock.try_clone().unwrap());
+            for (expected, answer) in preamble {
+                let mut line = String::new();
+                std::io::BufRead::read_line(&mut reader, &mut line).unwrap();
+                assert!(line.starts_with(expected), "{}", line);
+                sock.write_all(answer.as_bytes()).unwrap();
+            }
+        })
+    }
+
+    /// Runs `preamble` on the first accepted connection, then the TLS handshake of a localhost server
+    fn serve_tls_after<F>(preamble: F) -> u32 where F: FnOnce(&mut TcpStream) + Send + 'static {
+        serve_tls_with(rustls::ServerConfig::builder().with_no_client_auth(), preamble)
+    }
+
+    /// Serves the localhost chain with `builder`, which decides about client authentication
+    fn serve_tls_with<F>(builder: rustls::ConfigBuilder<rustls::ServerConfig, rustls::server::WantsServerCert>, preamble: F) -> u32
+    where F: FnOnce(&mut TcpStream) + Send + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            preamble(&mut sock);
+            let chain = vec![CertificateDer::from_pem_slice(LOCALHOST_CERT).unwrap(), CertificateDer::from_pem_slice(INTERMEDIATE_CERT).unwrap()];
+            let key = rustls::pki_types::PrivateKeyDer::from_pem_slice(LOCALHOST_KEY).unwrap();
+            let config = builder.with_single_cert(chain, key).unwrap();
+            let mut conn = rustls::ServerConnection::new(Arc::new(config)).unwrap();
+            while conn.is_handshaking() {
+                if conn.complete_io(&mut sock).is_err() {
+                    break;
+                }
+            }
+        });
+        port as u32
+    }
+
+    #[test]
+    fn test_cert_retriever_starttls() {
+        let port = serve_tls_once("220 mail ESMTP\r\n", &[("EHLO", "250-mail\r\n250 STARTTLS\r\n"), ("STARTTLS", "220 go ahead\r\n")]);
+        let options = ConnectOptions { starttls: Some(StartTls::Smtp), ..ConnectOptions::default() };
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("localhost", port, &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+        assert_eq!(cert.chain.len(), 2);
+        assert_eq!(cert.chain_order, ChainOrder::Ordered);
+
+        let port = serve_tls_once("220 mail ESMTP\r\n", &[("EHLO", "250 mail\r\n")]);
+        let e = CertRetriever::new().get_target_cert_from_endpoint("localhost", port, &options).err().unwrap();
+        assert_eq!(e.kind(), "StartTlsNotOffered", "{}", e);
+    }
+
+    #[test]
+    fn test_cert_retriever_postgres() {
+        let port = serve_tls_after(|sock| {
+            let mut request = [0u8; 8];
+            sock.read_exact(&mut request).unwrap();
+            assert_eq!(request, [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]);
+            sock.write_all(b"S").unwrap();
+        });
+        let site = Site::new("localhost", "postgres", port);
+        let options = ConnectOptions { proxy: None, ..ConnectOptions::for_site(&site).unwrap() };
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("localhost", port, &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+
+        let port = serve_tls_after(|sock| {
+            let mut request = [0u8; 8];
+            sock.read_exact(&mut request).unwrap();
+            sock.write_all(b"N").unwrap();
+        });
+        let site = Site::new("localhost", "PostgreSQL", port);
+        let options = ConnectOptions { proxy: None, ..ConnectOptions::for_site(&site).unwrap() };
+        let e = CertRetriever::new().get_target_cert_from_endpoint("localhost", port, &options).err().unwrap();
+        assert_eq!(e.kind(), "StartTlsNotOffered", "{}", e);
+    }
+
+    #[test]
+    fn test_cert_retriever_http_proxy() {
+        // the mock proxy answers the CONNECT and then serves TLS itself
+        let port = serve_tls_after(|sock| {
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                sock.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            assert!(request.starts_with(b"CONNECT localhost:8443 HTTP/1.1\r\n"));
+            sock.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap();
+        });
+        let proxy = Proxy::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+        let options = ConnectOptions { proxy: Some(proxy), ..ConnectOptions::default() };
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("localhost", 8443, &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+
+        let unused_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let proxy = Proxy::parse(&format!("socks5://127.0.0.1:{}", unused_port)).unwrap();
+        let options = ConnectOptions { proxy: Some(proxy), ..ConnectOptions::default() };
+        let e = CertRetriever::new().get_target_cert_from_endpoint("localhost", 8443, &options).err().unwrap();
+        assert_eq!(e.kind(), "ProxyFailed", "{}", e);
+        assert!(!e.is_transient());
+    }
+
+    #[test]
+    fn test_get_site_cert_per_address() {
+        let port = serve_tls_once("", &[]);
+        let mut site = Site::new("127.0.0.1", "other", port);
+        site.connection.proxy = Some(ProxySetting::Direct);
+        let outcomes = CertRetriever::new().get_site_cert_per_address(&site);
+        assert_eq!(outcomes.len(), 1);
+        let (address, result, attempts) = &outcomes[0];
+        assert_eq!(*address, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(result.as_ref().unwrap().certificate.get_common_name(), "localhost");
+        assert_eq!(*attempts, 1);
+
+        let site = Site::new("cert-monitor.invalid", "other", 443);
+        let outcomes = CertRetriever::new().get_site_cert_per_address(&Site { connection: ConnectionSettings {
+            retries: Some(0), proxy: Some(ProxySetting::Direct), ..ConnectionSettings::default() }, ..site });
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].0, None);
+        assert_eq!(outcomes[0].1.as_ref().err().unwrap().kind(), "DnsResolutionFailed");
+    }
+
+    #[test]
+    fn test_cert_retriever_connect_address_and_sni() {
+        let address = Some("127.0.0.1".parse().unwrap());
+        let port = serve_tls_once("", &[]);
+        let options = ConnectOptions { address, sni: Sni::Name("localhost".to_owned()), ..ConnectOptions::default() };
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("staging.cert-monitor.invalid", port, &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+
+        let port = serve_tls_once("", &[]);
+        let options = ConnectOptions { address, ..ConnectOptions::default() };
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("staging.cert-monitor.invalid", port, &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+
+        // without server name the default certificate is reported even though it does not match
+        let port = serve_tls_once("", &[]);
+        let options = ConnectOptions { address, sni: Sni::None, ..ConnectOptions::default() };
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("staging.cert-monitor.invalid", port, &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+        assert!(!cert.validation.unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_cert_retriever_name_mismatch() {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(ROOT_CERT).unwrap()).unwrap();
+        let retriever = CertRetriever::with_roots(roots);
+        let options = ConnectOptions { address: Some("127.0.0.1".parse().unwrap()), ..ConnectOptions::default() };
+
+        let cert = retriever.get_target_cert_from_endpoint("localhost", serve_tls_once("", &[]), &options).unwrap();
+        assert_eq!(cert.validation.unwrap().status, ValidationStatus::Valid);
+
+        // the trusted localhost chain served under another name is captured, the validation reports the mismatch
+        let cert = retriever.get_target_cert_from_endpoint("www.cert-monitor.invalid", serve_tls_once("", &[]), &options).unwrap();
+        assert_eq!(cert.certificate.get_common_name(), "localhost");
+        assert_eq!(cert.chain.len(), 2);
+        assert_eq!(cert.validation.unwrap().status, ValidationStatus::NameMismatch);
+    }
+
+    #[test]
+    fn test_connect_options_for_site() {
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "www.tschirky.ch", "service": "other",
+            "connectAddress": "10.0.0.7", "sni": "", "proxy": "direct" }"#).unwrap();
+        let options = ConnectOptions::for_site(&site).unwrap();
+        assert_eq!(options.address, Some("10.0.0.7".parse().unwrap()));
+        assert_eq!(options.sni, Sni::None);
+
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "www.tschirky.ch", "service": "other",
+            "sni": "new.tschirky.ch", "proxy": "direct" }"#).unwrap();
+        assert_eq!(ConnectOptions::for_site(&site).unwrap().sni, Sni::Name("new.tschirky.ch".to_owned()));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let addresses = resolve("127.0.0.1").unwrap();
+        assert_eq!(addresses, ["127.0.0.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(unbracketed("[::1]"), "::1");
+        assert_eq!(resolve(unbracketed("[::1]")).unwrap(), ["::1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_cert_retriever_client_auth() {
+        let roots = || {
+            let mut roots = RootCertStore::empty();
+            roots.add(CertificateDer::from_pem_slice(ROOT_CERT).unwrap()).unwrap();
+            roots
+        };
+        let requiring_client_auth = || {
+            let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots())).build().unwrap();
+            serve_tls_with(rustls::ServerConfig::builder().with_client_cert_verifier(verifier), |_| {})
+        };
+        let advertised = vec!["O=Cert Monitor Test, CN=Cert Monitor Test Root CA".to_owned()];
+
+        // TLS 1.3 completes the handshake on the client before the server checks the client certificate
+        let cert = CertRetriever::new().get_target_cert_from_endpoint("localhost", requiring_client_auth(), &ConnectOptions::default()).unwrap();
+        assert_eq!(cert.client_auth, ClientAuth { requested: true, presented: false, ca_names: advertised.clone() });
+
+        let client_key = load_client_certificate(&ClientCertificateConfig::Pem {
+            cert_path: "testdata/pki/client/client.crt".into(),
+            key_path: "testdata/pki/client/client.key".into(),
+        }).unwrap();
+        let retriever = CertRetriever::with_client_certificate(roots(), Some(client_key));
+        let cert = retriever.get_target_cert_from_endpoint("localhost", requiring_client_auth(), &ConnectOptions::default()).unwrap();
+        assert_eq!(cert.client_auth, ClientAuth { requested: true, presented: true, ca_names: advertised });
+
+        let cert = retriever.get_target_cert_from_endpoint("localhost", serve_tls_after(|_| {}), &ConnectOptions::default()).unwrap();
+        assert_eq!(cert.client_auth, ClientAuth::default());
+    }
+
+    #[test]
+    fn test_cert_retriever_handshake_alert() {
+        // fatal handshake_failure alert
+        let port = serve_once(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28]);
+        let e = CertRetriever::new().get_target_cert_from_endpoint("127.0.0.1", port, &ConnectOptions::default()).err().unwrap();
+        assert_eq!(e.kind(), "HandshakeAlert");
+        assert_eq!(e.alert_code(), Some(40));
+        assert_eq!(serde_json::to_value(&e).unwrap()["alertCode"], 40);
+    }
+
+    #[test]
+    fn test_cert_retriever_protocol_mismatch() {
+        let port = serve_once(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+        let e = CertRetriever::new().get_target_cert_from_endpoint("127.0.0.1", port, &ConnectOptions::default()).err().unwrap();
+        assert_eq!(e.kind(), "ProtocolMismatch", "{}", e);
+    }
+
+    #[test]
+    fn test_cert_retriever_closed_during_handshake() {
+        let port = serve_once(b"");
+        let e = CertRetriever::new().get_target_cert_from_endpoint("127.0.0.1", port, &ConnectOptions::default()).err().unwrap();
+        assert_eq!(e.kind(), "HandshakeFailed", "{}", e);
+    }
+
+    #[test]
+    fn test_cert_retriever_handshake_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port() as u32;
+        // accepted by the backlog, but never answered
+        let options = ConnectOptions {
+            timeouts: Timeouts { read: Duration::from_secs(1), ..Timeouts::default() },
+            ..ConnectOptions::default()
+        };
+        let e = CertRetriever::new().get_target_cert_from_endpoint("127.0.0.1", port, &options).err().unwrap();
+        assert_eq!(e.kind(), "HandshakeTimeout", "{}", e);
+        assert!(e.is_transient());
+        drop(listener);
+    }
+
+    #[test]
+    fn test_get_site_cert_retries() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let site: Site = serde_json::from_str(&format!(r#"{{ "targetFqn": "127.0.0.1", "service": "other", "port": {},
+            "retries": 2, "retryBackoffSeconds": 0 }}"#, port)).unwrap();
+        let (result, attempts) = CertRetriever::new().get_site_cert(&site);
+        assert_eq!(result.err().unwrap().kind(), "ConnectionRefused");
+        assert_eq!(attempts, 3);
+
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "not a host!", "service": "other", "retries": 2 }"#).unwrap();
+        let (result, attempts) = CertRetriever::new().get_site_cert(&site);
+        assert_eq!(result.err().unwrap().kind(), "InvalidServerName");
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_cert_retriever_invalid_server_name() {
+        let e = CertRetriever::new().get_target_cert_from_endpoint("not a host!", 443, &ConnectOptions::default()).err().unwrap();
+        assert_eq!(e.kind(), "InvalidServerName");
+        assert_eq!(e.to_string(), "InvalidServerName: not a host! is not a valid server name: invalid dns name");
+        let e = CertRetriever::new().get_target_cert_from_endpoint("localhost", 70000, &ConnectOptions::default()).err().unwrap();
+        assert_eq!(e.kind(), "InvalidServerName");
+    }
+
     #[test]
     fn test_write_to_pem(){
         let gitea = SimpleCertificate::from_certificate_der( &CertificateDer::from(GITEA_CERT)).unwrap();
@@ -0,0 +1,161 @@
+use crate::config::{ClientCertificateConfig, Password};
+use rustls::client::ResolvesClientCert;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::sign::CertifiedKey;
+use rustls::SignatureScheme;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use x509_parser::prelude::FromDer;
+use x509_parser::x509::X509Name;
+
+/// Whether the server asked for a client certificate during the handshake
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientAuth {
+    /// The server sent a CertificateRequest
+    pub requested: bool,
+    /// A client certificate was sent in response
+    pub presented: bool,
+    /// Distinguished names of the CAs the server accepts client certificates from, as advertised in
+    /// the CertificateRequest. Empty if the server accepts any.
+    pub ca_names: Vec<String>,
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))
+}
+
+/// Loads the certificate chain and private key described by `config`
+pub fn load_client_certificate(config: &ClientCertificateConfig) -> Result<Arc<CertifiedKey>, String> {
+    let (chain, key) = match config {
+        ClientCertificateConfig::Pem { cert_path, key_path } => {
+            let chain = CertificateDer::pem_file_iter(cert_path)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("Unable to read certificates from {}: {}", cert_path.display(), e))?;
+            let key = PrivateKeyDer::from_pem_file(key_path)
+                .map_err(|e| format!("Unable to read the private key from {}: {}", key_path.display(), e))?;
+            (chain, key)
+        }
+        ClientCertificateConfig::Pkcs12 { path, password } => {
+            let password = password.as_ref().map(Password::resolve).transpose()?.unwrap_or_default();
+            let store = p12_keystore::KeyStore::from_pkcs12(&read_file(path)?, &password)
+                .map_err(|e| format!("Unable to read the PKCS#12 file {}: {}", path.display(), e))?;
+            let (_, key_chain) = store.private_key_chain()
+                .ok_or_else(|| format!("The PKCS#12 file {} contains no private key", path.display()))?;
+            let chain = key_chain.chain().iter()
+                .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+                .collect();
+            (chain, PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_chain.key().to_vec())))
+        }
+    };
+    if chain.is_empty() {
+        return Err(format!("{} contains no certificate", config.path().display()));
+    }
+    let key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported private key in {}: {}", config.path().display(), e))?;
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+/// Presents the configured client certificate, if any, and records whether the server asked for one.
+/// Used for a single connection only.
+#[derive(Debug)]
+pub struct ClientAuthRecorder {
+    certified_key: Option<Arc<CertifiedKey>>,
+    client_auth: Mutex<ClientAuth>,
+}
+
+impl ClientAuthRecorder {
+    pub fn new(certified_key: Option<Arc<CertifiedKey>>) -> ClientAuthRecorder {
+        ClientAuthRecorder { certified_key, client_auth: Mutex::new(ClientAuth::default()) }
+    }
+
+    pub fn client_auth(&self) -> ClientAuth {
+        self.client_auth.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl ResolvesClientCert for ClientAuthRecorder {
+    fn resolve(&self, root_hint_subjects: &[&[u8]], sigschemes: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        // only called if the server sent a CertificateRequest
+        let presented = self.certified_key.as_ref()
+            .filter(|certified_key| certified_key.key.choose_scheme(sigschemes).is_some());
+        *self.client_auth.lock().unwrap_or_else(|e| e.into_inner()) = ClientAuth {
+            requested: true,
+            presented: presented.is_some(),
+            ca_names: root_hint_subjects.iter().map(|subject| distinguished_name(subject)).collect(),
+        };
+        presented.cloned()
+    }
+
+    fn has_certs(&self) -> bool {
+        self.certified_key.is_some()
+    }
+}
+
+/// Readable form of a DER encoded distinguished name, hex if it can't be parsed
+fn distinguished_name(der: &[u8]) -> String {
+    match X509Name::from_der(der) {
+        Ok((_, name)) => name.to_string(),
+        Err(_) => data_encoding::HEXLOWER.encode(der),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_load_pem() {
+        let certified_key = load_client_certificate(&ClientCertificateConfig::Pem {
+            cert_path: PathBuf::from("testdata/pki/client/client.crt"),
+            key_path: PathBuf::from("testdata/pki/client/client.key"),
+        }).unwrap();
+        assert_eq!(certified_key.cert.len(), 1);
+        assert!(certified_key.keys_match().is_ok());
+    }
+
+    #[test]
+    fn test_load_pkcs12() {
+        let certified_key = load_client_certificate(&ClientCertificateConfig::Pkcs12 {
+            path: PathBuf::from("testdata/pki/client/client.p12"),
+            password: Some(Password::Value("changeit".to_owned())),
+        }).unwrap();
+        assert_eq!(certified_key.cert.len(), 2);
+        assert!(certified_key.keys_match().is_ok());
+
+        assert!(load_client_certificate(&ClientCertificateConfig::Pkcs12 {
+            path: PathBuf::from("testdata/pki/client/client.p12"),
+            password: Some(Password::Value("wrong".to_owned())),
+        }).is_err());
+        assert!(load_client_certificate(&ClientCertificateConfig::Pkcs12 {
+            path: PathBuf::from("testdata/pki/client/client.p12"),
+            password: None,
+        }).is_err());
+
+        // the same password as the keystores
+        let certified_key = load_client_certificate(&ClientCertificateConfig::Pkcs12 {
+            path: PathBuf::from("testdata/pki/client/client.p12"),
+            password: Some(Password::File(PathBuf::from("testdata/pki/keystores/password.txt"))),
+        }).unwrap();
+        assert_eq!(certified_key.cert.len(), 2);
+        let unset = load_client_certificate(&ClientCertificateConfig::Pkcs12 {
+            path: PathBuf::from("testdata/pki/client/client.p12"),
+            password: Some(Password::Env("CERT_MONITOR_TEST_UNSET".to_owned())),
+        });
+        assert!(unset.err().unwrap().starts_with("Unable to read the password from the environment variable CERT_MONITOR_TEST_UNSET"));
+    }
+
+    #[test]
+    fn test_recorder() {
+        let recorder = ClientAuthRecorder::new(None);
+        assert_eq!(recorder.client_auth(), ClientAuth::default());
+        // SEQUENCE { SET { SEQUENCE { OID commonName, UTF8String "Test CA" } } }
+        let name = [0x30, 0x12, 0x31, 0x10, 0x30, 0x0e, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x07, b'T', b'e', b's', b't', b' ', b'C', b'A'];
+        assert!(recorder.resolve(&[&name], &[SignatureScheme::ECDSA_NISTP256_SHA256]).is_none());
+        assert_eq!(recorder.client_auth(), ClientAuth { requested: true, presented: false, ca_names: vec!["CN=Test CA".to_owned()] });
+    }
+}
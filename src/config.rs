@@ -1,7 +1,17 @@
+use crate::cert_retriever::Timeouts;
+use crate::policy::Policy;
+use crate::pool::Parallelism;
+use crate::proxy::ProxySetting;
+use crate::starttls::StartTls;
+use crate::severity::{default_levels, warning_critical_levels, SeverityLevel};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 
 fn default_target_port() -> u32 {
@@ -12,14 +22,59 @@ fn default_log_target () -> String {
     "cert-monitor".to_owned()
 }
 
-fn default_min_valid_days() -> i64 { 15 }
+fn default_warning_days() -> i64 { 15 }
 
-pub fn load_config_file( file : PathBuf) -> Option<SiteConfig>{
+fn default_critical_days() -> i64 { 7 }
+
+fn default_validate_chain() -> bool { true }
+
+fn default_jitter_seconds() -> u64 { 300 }
+
+fn default_workers() -> usize { 8 }
+
+fn default_max_connections_per_host() -> usize { 2 }
+
+fn default_timeout_seconds() -> u64 { 10 }
+
+fn default_retries() -> u32 { 2 }
+
+fn default_retry_backoff_seconds() -> u64 { 1 }
+
+fn default_log_max_size_bytes() -> u64 { 10 * 1024 * 1024 }
+
+fn default_log_max_files() -> u32 { 5 }
+
+fn default_syslog_socket() -> PathBuf { PathBuf::from("/dev/log") }
+
+fn default_journald_socket() -> PathBuf { PathBuf::from("/run/systemd/journal/socket") }
+
+/// Length of a base64 encoded SHA-256 digest
+const BASE64_SHA256_LEN: usize = 44;
+
+/// The base64 digest of `pin` without its `sha256/` or curl's `sha256//` prefix. As a digest may start with
+/// a slash itself, `sha256//` is only taken as prefix if a complete digest follows it
+pub fn pin_digest(pin: &str) -> &str {
+    match pin.strip_prefix("sha256//") {
+        Some(digest) if digest.len() == BASE64_SHA256_LEN => digest,
+        _ => pin.strip_prefix("sha256/").unwrap_or(pin),
+    }
+}
+
+/// Parses a pin of a site or of `check --pin`, which must be a base64 SHA-256 digest
+pub fn parse_pin(pin: &str) -> Result<String, String> {
+    match data_encoding::BASE64.decode(pin_digest(pin).as_bytes()) {
+        Ok(digest) if digest.len() == 32 => Ok(pin.to_owned()),
+        _ => Err(format!("Invalid pin {}, expected a base64 SHA-256 digest", pin)),
+    }
+}
+
+pub fn load_config_file( file : &Path) -> Option<SiteConfig>{
     if file.exists() {
         if let Ok(file) = File::open(file) {
             let reader = BufReader::new(file);
             let config: Result<SiteConfig, serde_json::Error>   = serde_json::from_reader(reader);
-            if let Ok(config) = config {
+            if let Ok(mut config) = config {
+                config.inherit_defaults();
                 return Some(config);
             }
         }
@@ -33,7 +88,47 @@ pub struct SiteConfig {
     sites: Vec<Site>,
 
     #[serde(default = "default_log_target")]
-    pub log_target: String
+    pub log_target: String,
+
+    /// Where the log records of the daemon mode are written to
+    #[serde(default)]
+    pub log_sink: LogSinkConfig,
+
+    /// Upper bound of the random delay added to the first check of each site in monitor mode
+    #[serde(default = "default_jitter_seconds")]
+    pub jitter_seconds: u64,
+
+    /// Severity levels of all sites which define neither own levels nor thresholds
+    #[serde(default)]
+    pub severity_levels: Option<Vec<SeverityLevel>>,
+
+    /// Named sets of trust anchors which sites can reference by `trustStore`
+    #[serde(default)]
+    pub trust_stores: BTreeMap<String, TrustStoreConfig>,
+
+    /// Named client certificates which sites can reference by `clientCertificate`
+    #[serde(default)]
+    pub client_certificates: BTreeMap<String, ClientCertificateConfig>,
+
+    /// Named certificate requirements which apply to the sites referencing them by `policies` or by their `tags`
+    #[serde(default)]
+    pub policies: BTreeMap<String, Policy>,
+
+    /// JSON file remembering the certificate last seen at each endpoint, to report changes between runs
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+
+    /// Number of sites checked at the same time
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+
+    /// Number of sites with the same target host checked at the same time
+    #[serde(default = "default_max_connections_per_host")]
+    pub max_connections_per_host: usize,
+
+    /// Timeouts, retries and proxy of all sites which don't configure their own
+    #[serde(flatten)]
+    pub connection: ConnectionSettings
 }
 
 
@@ -43,18 +138,307 @@ impl SiteConfig {
         self.sites.iter()
     }
 
-    pub fn simple(target_fqn: &String, target_port: u32, min_valid_days : i64) -> SiteConfig {
+    pub fn site_iter_mut(&mut self) -> impl Iterator<Item=&mut Site> {
+        self.sites.iter_mut()
+    }
+
+    /// Config with the given sites and all other settings at their defaults
+    pub fn new(sites: Vec<Site>) -> SiteConfig {
+        SiteConfig {
+            sites,
+            log_target: default_log_target(),
+            log_sink: LogSinkConfig::Stderr,
+            jitter_seconds: default_jitter_seconds(),
+            severity_levels: None,
+            trust_stores: BTreeMap::new(),
+            client_certificates: BTreeMap::new(),
+            policies: BTreeMap::new(),
+            state_file: None,
+            workers: default_workers(),
+            max_connections_per_host: default_max_connections_per_host(),
+            connection: ConnectionSettings::default(),
+        }
+    }
+
+    pub fn parallelism(&self) -> Parallelism {
+        Parallelism {
+            workers: self.workers.max(1),
+            per_host: self.max_connections_per_host.max(1),
+        }
+    }
+
+    pub fn simple(target_fqn: &str, target_port: u32, warning_days : i64, critical_days : i64) -> SiteConfig {
+        SiteConfig {
+            log_target: "".to_string(),
+            jitter_seconds: 0,
+            ..SiteConfig::new(vec![ Site {
+                severity_levels: Some(warning_critical_levels(warning_days, critical_days)),
+                ..Site::new(target_fqn, "query", target_port)
+            }])
+        }
+    }
+
+    /// Inspects the certificate files and directories at `paths`
+    pub fn files(paths: &[PathBuf], warning_days : i64, critical_days : i64) -> SiteConfig {
         SiteConfig {
-            sites: vec![ Site {
-                target_fqn: target_fqn.to_owned(),
-                service: "query".to_string(),
-                port: target_port,
-                min_valid_days,
-            }
-            ],
             log_target: "".to_string(),
+            jitter_seconds: 0,
+            ..SiteConfig::new(paths.iter().map(|path| Site {
+                severity_levels: Some(warning_critical_levels(warning_days, critical_days)),
+                ..Site::file(path, "inspect")
+            }).collect())
+        }
+    }
+
+    /// Registers `trust_store` under `name` and lets all sites validate against it
+    pub fn add_trust_store(&mut self, name: &str, trust_store: TrustStoreConfig) {
+        self.trust_stores.insert(name.to_owned(), trust_store);
+        self.sites.iter_mut().for_each(|site| site.trust_store = Some(name.to_owned()));
+    }
+
+    /// Registers `client_certificate` under `name` and lets all sites present it
+    pub fn add_client_certificate(&mut self, name: &str, client_certificate: ClientCertificateConfig) {
+        self.client_certificates.insert(name.to_owned(), client_certificate);
+        self.sites.iter_mut().for_each(|site| site.client_certificate = Some(name.to_owned()));
+    }
+
+    /// The policies which apply to `site`, the ones it references by name and the ones matching its tags
+    pub fn site_policies(&self, site: &Site) -> Vec<(&str, &Policy)> {
+        self.policies.iter()
+            .filter(|(name, policy)| site.policies.contains(name) || policy.matches_tags(&site.tags))
+            .map(|(name, policy)| (name.as_str(), policy))
+            .collect()
+    }
+
+    /// Fails if a pin of a site is no base64 SHA-256 digest
+    pub fn check_pins(&self) -> Result<(), String> {
+        for site in &self.sites {
+            if let Some(pin) = site.pins.iter().find(|pin| parse_pin(pin).is_err()) {
+                return Err(format!("Invalid pin {} of site {}, expected a base64 SHA-256 digest", pin, site.target_fqn));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if a site references a policy which is not declared
+    pub fn check_policy_references(&self) -> Result<(), String> {
+        for site in &self.sites {
+            if let Some(name) = site.policies.iter().find(|name| !self.policies.contains_key(*name)) {
+                return Err(format!("Unknown policy {} of site {}", name, site.target_fqn));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands the top level severity levels and connection settings down to the sites which don't configure their own
+    pub fn inherit_defaults(&mut self) {
+        if let Some(levels) = &self.severity_levels {
+            self.sites.iter_mut()
+                .filter(|site| site.severity_levels.is_none() && !site.has_legacy_thresholds())
+                .for_each(|site| site.severity_levels = Some(levels.clone()));
+        }
+        for site in self.sites.iter_mut() {
+            site.connection = site.connection.or(&self.connection);
+            if site.target_fqn.is_empty() {
+                if let Some(path) = &site.path {
+                    site.target_fqn = path.display().to_string();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "lowercase", rename_all_fields = "camelCase")]
+#[derive(Debug)]
+pub enum LogSinkConfig {
+    #[default]
+    Stderr,
+
+    /// Plain text file which is rotated once it reaches `max_size_bytes`
+    File {
+        path: PathBuf,
+
+        #[serde(default = "default_log_max_size_bytes")]
+        max_size_bytes: u64,
+
+        /// Number of rotated files (`<path>.1` .. `<path>.n`) to keep
+        #[serde(default = "default_log_max_files")]
+        max_files: u32
+    },
+
+    /// RFC 5424 formatted messages sent to the local syslog daemon
+    Syslog {
+        #[serde(default = "default_syslog_socket")]
+        socket: PathBuf
+    },
+
+    /// Native protocol of systemd-journald
+    Journald {
+        #[serde(default = "default_journald_socket")]
+        socket: PathBuf
+    }
+}
+
+/// Timeouts, retries and proxy of the connection to a site. Unset values are taken from the top level of the
+/// config file and then from the defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_seconds: Option<u64>,
+
+    /// Upper bound of the whole TLS handshake
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handshake_timeout_seconds: Option<u64>,
+
+    /// Upper bound of each single read from and write to the socket
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_timeout_seconds: Option<u64>,
+
+    /// Additional attempts after a transient failure like a timeout or a refused connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+
+    /// Delay before the first retry, doubled for each further one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_backoff_seconds: Option<u64>,
+
+    /// Proxy URL or `direct`, `HTTPS_PROXY` and `NO_PROXY` apply if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxySetting>,
+}
+
+impl ConnectionSettings {
+    /// The settings of `self`, with the unset ones taken from `defaults`
+    pub fn or(&self, defaults: &ConnectionSettings) -> ConnectionSettings {
+        ConnectionSettings {
+            connect_timeout_seconds: self.connect_timeout_seconds.or(defaults.connect_timeout_seconds),
+            handshake_timeout_seconds: self.handshake_timeout_seconds.or(defaults.handshake_timeout_seconds),
+            read_timeout_seconds: self.read_timeout_seconds.or(defaults.read_timeout_seconds),
+            retries: self.retries.or(defaults.retries),
+            retry_backoff_seconds: self.retry_backoff_seconds.or(defaults.retry_backoff_seconds),
+            proxy: self.proxy.clone().or_else(|| defaults.proxy.clone()),
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        // a timeout of zero is not accepted by the socket
+        let seconds = |value: Option<u64>| Duration::from_secs(value.unwrap_or(default_timeout_seconds()).max(1));
+        Timeouts {
+            connect: seconds(self.connect_timeout_seconds),
+            handshake: seconds(self.handshake_timeout_seconds),
+            read: seconds(self.read_timeout_seconds),
+        }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(default_retries())
+    }
+
+    /// Delay before the given retry, starting at 1
+    pub fn retry_backoff(&self, retry: u32) -> Duration {
+        let initial = self.retry_backoff_seconds.unwrap_or(default_retry_backoff_seconds());
+        Duration::from_secs(initial.saturating_mul(1 << retry.saturating_sub(1).min(16)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", rename_all_fields = "camelCase")]
+pub enum TrustStoreConfig {
+    /// The Mozilla roots compiled into the binary, used by sites without `trustStore`
+    Builtin,
+
+    /// PEM bundle with one or more CA certificates
+    File {
+        path: PathBuf
+    },
+
+    /// Directory whose `*.pem`, `*.crt` and `*.cer` files contain CA certificates
+    Directory {
+        path: PathBuf
+    },
+
+    /// CA bundle of the operating system, looked up in the usual locations below /etc/ssl
+    /// and /etc/pki unless `path` is given
+    System {
+        #[serde(default)]
+        path: Option<PathBuf>
+    }
+}
+
+/// Certificate and private key presented to servers which request client authentication
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", rename_all_fields = "camelCase")]
+pub enum ClientCertificateConfig {
+    /// PEM files with the certificate chain, starting with the client certificate, and the private key
+    Pem {
+        cert_path: PathBuf,
+        key_path: PathBuf
+    },
+
+    /// PKCS#12 file with the certificate chain and the private key
+    Pkcs12 {
+        path: PathBuf,
+        /// Password of the file, an empty one if there is none
+        #[serde(default)]
+        password: Option<Password>
+    }
+}
+
+impl ClientCertificateConfig {
+    /// The file with the certificate, for messages
+    pub fn path(&self) -> &Path {
+        match self {
+            ClientCertificateConfig::Pem { cert_path, .. } => cert_path,
+            ClientCertificateConfig::Pkcs12 { path, .. } => path,
+        }
+    }
+}
+
+/// Where the certificate of a site comes from
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SiteType {
+    /// Retrieved in the TLS handshake with the target
+    #[default]
+    Tls,
+    /// Read from the certificate file or directory at `path`, without any network connection
+    File,
+}
+
+/// Secret given in the config file, by an environment variable or in a file of its own
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Password {
+    Value(String),
+    /// Name of the environment variable
+    Env(String),
+    /// The first line of the file is the password
+    File(PathBuf),
+}
+
+impl Password {
+    pub fn resolve(&self) -> Result<String, String> {
+        match self {
+            Password::Value(password) => Ok(password.clone()),
+            Password::Env(name) => std::env::var(name)
+                .map_err(|e| format!("Unable to read the password from the environment variable {}: {}", name, e)),
+            Password::File(path) => std::fs::read_to_string(path)
+                .map(|content| content.lines().next().unwrap_or_default().to_owned())
+                .map_err(|e| format!("Unable to read the password from {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Keeps the password out of messages and logs
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Password::Value(_) => write!(f, "Value(***)"),
+            Password::Env(name) => write!(f, "Env({})", name),
+            Password::File(path) => write!(f, "File({})", path.display()),
         }
-        
     }
 }
 
@@ -62,14 +446,157 @@ impl SiteConfig {
 #[serde(rename_all = "camelCase")]
 #[derive(Debug)]
 pub struct Site {
+    /// Host name or IP address of a `tls` site, defaults to the `path` of a `file` site
+    #[serde(default)]
     pub target_fqn: String,
     pub service : String,
 
+    #[serde(rename = "type", default)]
+    pub site_type : SiteType,
+
+    /// Certificate file (PEM, PEM bundle, DER, PKCS#12, JKS or JCEKS) or directory of a `file` site
+    #[serde(default)]
+    pub path : Option<PathBuf>,
+
+    /// Password of the PKCS#12, JKS and JCEKS keystores at `path`
+    #[serde(default)]
+    pub keystore_password : Option<Password>,
+
     #[serde(default = "default_target_port")]
     pub port : u32,
     
-    #[serde(default = "default_min_valid_days")]
-    pub min_valid_days : i64
+    /// Shorthand for a `warning` level, superseded by `severity_levels`
+    #[serde(default)]
+    pub min_valid_days : Option<i64>,
+
+    /// Shorthand for a `warning` level, superseded by `severity_levels`
+    #[serde(default)]
+    pub warning_days : Option<i64>,
+
+    /// Shorthand for a `critical` level, superseded by `severity_levels`
+    #[serde(default)]
+    pub critical_days : Option<i64>,
+
+    #[serde(default)]
+    pub severity_levels : Option<Vec<SeverityLevel>>,
+
+    /// Overrides the monitor interval given on the command line for this site
+    #[serde(default)]
+    pub interval_hours : Option<u32>,
+
+    /// Whether a certificate chain which fails validation raises the state to critical. The
+    /// validation result is reported in any case.
+    #[serde(default = "default_validate_chain")]
+    pub validate_chain : bool,
+
+    /// Name of the entry in `trustStores` to validate the chain against, the builtin roots if absent
+    #[serde(default)]
+    pub trust_store : Option<String>,
+
+    /// Plaintext protocol to upgrade the connection with before the handshake, implicit TLS if absent
+    #[serde(default)]
+    pub starttls : Option<StartTls>,
+
+    /// Checks every address the target resolves to instead of the first one accepting the connection
+    #[serde(default)]
+    pub all_addresses : bool,
+
+    /// Name of the entry in `clientCertificates` to present if the server requests client authentication
+    #[serde(default)]
+    pub client_certificate : Option<String>,
+
+    /// Address to connect to instead of resolving the target, e.g. a backend behind a load balancer
+    #[serde(default)]
+    pub connect_address : Option<IpAddr>,
+
+    /// Server name sent in the handshake and expected in the certificate instead of the target,
+    /// no server name at all if empty
+    #[serde(default)]
+    pub sni : Option<String>,
+
+    /// Ids of the lint rules not to apply to the certificates of this site
+    #[serde(default)]
+    pub disabled_lints : Vec<String>,
+
+    /// Labels which select the `policies` applying to this site, e.g. `public` or `payment`
+    #[serde(default)]
+    pub tags : Vec<String>,
+
+    /// Names of the entries in `policies` to check the certificate against, besides the ones selected by `tags`
+    #[serde(default)]
+    pub policies : Vec<String>,
+
+    /// Base64 SHA-256 digests of subject public key infos, optionally prefixed by `sha256/`, of which one
+    /// certificate of the presented chain must match one, e.g. the key of the intermediate CA
+    #[serde(default)]
+    pub pins : Vec<String>,
+
+    #[serde(flatten)]
+    pub connection : ConnectionSettings
+}
+
+impl Site {
+    /// Site with all optional settings at their defaults
+    pub fn new(target_fqn: &str, service: &str, port: u32) -> Site {
+        Site {
+            target_fqn: target_fqn.to_owned(),
+            service: service.to_owned(),
+            site_type: SiteType::Tls,
+            path: None,
+            keystore_password: None,
+            port,
+            min_valid_days: None,
+            warning_days: None,
+            critical_days: None,
+            severity_levels: None,
+            interval_hours: None,
+            validate_chain: default_validate_chain(),
+            trust_store: None,
+            starttls: None,
+            all_addresses: false,
+            client_certificate: None,
+            connect_address: None,
+            sni: None,
+            disabled_lints: Vec::new(),
+            tags: Vec::new(),
+            policies: Vec::new(),
+            pins: Vec::new(),
+            connection: ConnectionSettings::default(),
+        }
+    }
+
+    /// `file` site which reads the certificates at `path`
+    pub fn file(path: &Path, service: &str) -> Site {
+        Site {
+            site_type: SiteType::File,
+            path: Some(path.to_owned()),
+            ..Site::new(&path.display().to_string(), service, 0)
+        }
+    }
+
+    fn has_legacy_thresholds(&self) -> bool {
+        self.min_valid_days.is_some() || self.warning_days.is_some() || self.critical_days.is_some()
+    }
+
+    /// The digests of `pins` without the `sha256/` prefix, curl's `sha256//` included
+    pub fn pin_digests(&self) -> impl Iterator<Item=&str> {
+        self.pins.iter().map(|pin| pin_digest(pin))
+    }
+
+    /// The configured severity levels or, if there are none, the ones built from the
+    /// `warning_days`/`min_valid_days` and `critical_days` thresholds
+    pub fn severity_levels(&self) -> Vec<SeverityLevel> {
+        if let Some(levels) = &self.severity_levels {
+            levels.clone()
+        } else if self.has_legacy_thresholds() {
+            let warning_days = self.warning_days.or(self.min_valid_days).unwrap_or(default_warning_days());
+            // the critical threshold never exceeds the warning threshold
+            let critical_days = self.critical_days.unwrap_or(default_critical_days()).min(warning_days);
+            warning_critical_levels(warning_days, critical_days)
+        } else {
+            default_levels()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -78,22 +605,198 @@ mod tests {
     #[test]
     fn serialize_test() {
         let s1 = Site {
-            target_fqn: "www.tschirky.ch".to_string(),
-            service: "flup".to_string(),
-            port: 4444,
-            min_valid_days: 15,
+            min_valid_days: Some(15),
+            ..Site::new("www.tschirky.ch", "flup", 4444)
         };
         let s2 = Site {
-            target_fqn: "gitea.tschirky.ch".to_string(),
-            service: "other".to_string(),
-            port: 443,
-            min_valid_days: 15,
+            min_valid_days: Some(15),
+            ..Site::new("gitea.tschirky.ch", "other", 443)
         };
         let cfg = SiteConfig {
-            sites: vec![s1,s2],
             log_target: "monitor".to_string(),
+            ..SiteConfig::new(vec![s1,s2])
         };
         let serialized = serde_json::to_string(&cfg).unwrap();
         println!("{:?}", serialized);
     }
+
+    #[test]
+    fn log_sink_test() {
+        let cfg: SiteConfig = serde_json::from_str(r#"{
+            "sites": [],
+            "logSink": { "type": "file", "path": "/var/log/cert-monitor.log", "maxFiles": 3 }
+        }"#).unwrap();
+        match cfg.log_sink {
+            LogSinkConfig::File { path, max_size_bytes, max_files } => {
+                assert_eq!(path, PathBuf::from("/var/log/cert-monitor.log"));
+                assert_eq!(max_size_bytes, 10 * 1024 * 1024);
+                assert_eq!(max_files, 3);
+            }
+            _ => panic!("Unexpected log sink"),
+        }
+
+        let cfg: SiteConfig = serde_json::from_str(r#"{ "sites": [] }"#).unwrap();
+        assert!(matches!(cfg.log_sink, LogSinkConfig::Stderr));
+    }
+
+    #[test]
+    fn thresholds_test() {
+        let cfg: SiteConfig = serde_json::from_str(r#"{ "sites": [
+            { "targetFqn": "www.tschirky.ch", "service": "flup" },
+            { "targetFqn": "gitea.tschirky.ch", "service": "other", "minValidDays": 20, "criticalDays": 10 },
+            { "targetFqn": "owncloud.tschirky.ch", "service": "other", "warningDays": 30, "criticalDays": 40 }
+        ]}"#).unwrap();
+        let thresholds: Vec<(i64, i64)> = cfg.site_iter().map(|s| {
+            let levels = s.severity_levels();
+            (levels[0].days, levels[1].days)
+        }).collect();
+        assert_eq!(thresholds, [(15, 7), (20, 10), (30, 30)]);
+    }
+
+    #[test]
+    fn severity_level_inheritance_test() {
+        let mut cfg: SiteConfig = serde_json::from_str(r#"{
+            "severityLevels": [
+                { "name": "info", "days": 45, "state": "ok" },
+                { "name": "warn", "days": 21, "state": "warning" },
+                { "name": "critical", "days": 7, "state": "critical" }
+            ],
+            "sites": [
+                { "targetFqn": "www.tschirky.ch", "service": "flup" },
+                { "targetFqn": "gitea.tschirky.ch", "service": "other", "minValidDays": 20 },
+                { "targetFqn": "owncloud.tschirky.ch", "service": "other", "severityLevels": [ { "name": "soon", "days": 3 } ] }
+            ]
+        }"#).unwrap();
+        cfg.inherit_defaults();
+        let names: Vec<Vec<String>> = cfg.site_iter()
+            .map(|s| s.severity_levels().into_iter().map(|l| l.name).collect())
+            .collect();
+        assert_eq!(names, [vec!["info", "warn", "critical"], vec!["warning", "critical"], vec!["soon"]]);
+    }
+
+    #[test]
+    fn connection_settings_test() {
+        let mut cfg: SiteConfig = serde_json::from_str(r#"{
+            "connectTimeoutSeconds": 3,
+            "retries": 4,
+            "sites": [
+                { "targetFqn": "www.tschirky.ch", "service": "flup" },
+                { "targetFqn": "gitea.tschirky.ch", "service": "other", "connectTimeoutSeconds": 30, "retries": 0, "retryBackoffSeconds": 5 }
+            ]
+        }"#).unwrap();
+        cfg.inherit_defaults();
+        let sites: Vec<&Site> = cfg.site_iter().collect();
+
+        assert_eq!(sites[0].connection.timeouts(), Timeouts {
+            connect: Duration::from_secs(3),
+            handshake: Duration::from_secs(10),
+            read: Duration::from_secs(10),
+        });
+        assert_eq!(sites[0].connection.retries(), 4);
+        assert_eq!(sites[0].connection.retry_backoff(1), Duration::from_secs(1));
+        assert_eq!(sites[0].connection.retry_backoff(3), Duration::from_secs(4));
+
+        assert_eq!(sites[1].connection.timeouts().connect, Duration::from_secs(30));
+        assert_eq!(sites[1].connection.retries(), 0);
+        assert_eq!(sites[1].connection.retry_backoff(2), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn proxy_test() {
+        let mut cfg: SiteConfig = serde_json::from_str(r#"{
+            "proxy": "http://proxy.tschirky.ch:3128",
+            "sites": [
+                { "targetFqn": "www.tschirky.ch", "service": "flup" },
+                { "targetFqn": "gitea.tschirky.ch", "service": "other", "proxy": "direct" },
+                { "targetFqn": "owncloud.tschirky.ch", "service": "other", "proxy": "socks5://socks.tschirky.ch" }
+            ]
+        }"#).unwrap();
+        cfg.inherit_defaults();
+        let proxies: Vec<String> = cfg.site_iter()
+            .map(|site| site.connection.proxy.clone().map(String::from).unwrap_or_default())
+            .collect();
+        assert_eq!(proxies, ["http://proxy.tschirky.ch:3128", "direct", "socks5://socks.tschirky.ch"]);
+
+        assert!(serde_json::from_str::<SiteConfig>(r#"{ "proxy": "ftp://proxy", "sites": [] }"#).is_err());
+    }
+
+    #[test]
+    fn file_site_test() {
+        let mut cfg: SiteConfig = serde_json::from_str(r#"{
+            "sites": [
+                { "targetFqn": "www.tschirky.ch", "service": "flup" },
+                { "type": "file", "path": "/etc/nginx/ssl/www.pem", "service": "nginx" },
+                { "type": "file", "path": "/etc/haproxy/certs", "service": "haproxy", "targetFqn": "haproxy" }
+            ]
+        }"#).unwrap();
+        cfg.inherit_defaults();
+        let sites: Vec<(SiteType, &str)> = cfg.site_iter().map(|site| (site.site_type, site.target_fqn.as_str())).collect();
+        assert_eq!(sites, [(SiteType::Tls, "www.tschirky.ch"), (SiteType::File, "/etc/nginx/ssl/www.pem"), (SiteType::File, "haproxy")]);
+    }
+
+    #[test]
+    fn password_test() {
+        let site: Site = serde_json::from_str(r#"{ "type": "file", "path": "/opt/kafka/ssl/kafka.jks", "service": "kafka",
+            "keystorePassword": { "env": "CERT_MONITOR_TEST_UNSET" } }"#).unwrap();
+        let password = site.keystore_password.unwrap();
+        assert_eq!(password, Password::Env("CERT_MONITOR_TEST_UNSET".to_owned()));
+        assert!(password.resolve().is_err());
+
+        assert_eq!(Password::Value("changeit".to_owned()).resolve().unwrap(), "changeit");
+        assert_eq!(format!("{:?}", Password::Value("changeit".to_owned())), "Value(***)");
+        assert_eq!(Password::File(PathBuf::from("testdata/pki/keystores/password.txt")).resolve().unwrap(), "changeit");
+    }
+
+    #[test]
+    fn policies_test() {
+        let cfg: SiteConfig = serde_json::from_str(r#"{
+            "policies": {
+                "public": { "tags": [ "public" ], "allowedIssuers": [ "Let's Encrypt" ] },
+                "payment": { "wildcardsAllowed": false, "state": "critical" }
+            },
+            "sites": [
+                { "targetFqn": "www.tschirky.ch", "service": "flup", "tags": [ "public" ] },
+                { "targetFqn": "pay.tschirky.ch", "service": "flup", "tags": [ "public" ], "policies": [ "payment" ] },
+                { "targetFqn": "intranet.tschirky.ch", "service": "other" }
+            ]
+        }"#).unwrap();
+        let names: Vec<Vec<&str>> = cfg.site_iter()
+            .map(|site| cfg.site_policies(site).iter().map(|(name, _)| *name).collect())
+            .collect();
+        assert_eq!(names, [vec!["public"], vec!["payment", "public"], vec![]]);
+        assert!(cfg.check_policy_references().is_ok());
+
+        let cfg: SiteConfig = serde_json::from_str(r#"{
+            "sites": [ { "targetFqn": "pay.tschirky.ch", "service": "flup", "policies": [ "payment" ] } ]
+        }"#).unwrap();
+        assert_eq!(cfg.check_policy_references().unwrap_err(), "Unknown policy payment of site pay.tschirky.ch");
+    }
+
+    #[test]
+    fn pins_test() {
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "www.tschirky.ch", "service": "flup", "pins": [
+            "h5bNchq3WpsWRNuTdNRvRaHTMBkrUFBN0WF4goIz+0Q=",
+            "sha256/Ogxk8WSHuZAFPfLtCQv/OBMeuqStz1q99PEerdc8RMo=",
+            "/9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8=",
+            "sha256///9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8=",
+            "sha256//9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8="
+        ] }"#).unwrap();
+        // a digest may start with a slash itself, only the prefix is stripped
+        assert_eq!(site.pin_digests().collect::<Vec<_>>(), [
+            "h5bNchq3WpsWRNuTdNRvRaHTMBkrUFBN0WF4goIz+0Q=",
+            "Ogxk8WSHuZAFPfLtCQv/OBMeuqStz1q99PEerdc8RMo=",
+            "/9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8=",
+            "/9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8=",
+            // `sha256/` followed by a digest starting with a slash
+            "/9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8=",
+        ]);
+        assert!(site.pins.iter().all(|pin| parse_pin(pin).is_ok()));
+
+        assert!(parse_pin("sha256/9tZ2v0rOhHkQmgAQ0DK1yZ3cn7Vd0Ccxs0gMklwWz8=").is_err());
+        assert!(parse_pin("sha256//7b7041eff2eb728b69f308c3a950f1f08ff197cb").is_err());
+        let cfg: SiteConfig = serde_json::from_str(r#"{ "sites": [
+            { "targetFqn": "www.tschirky.ch", "service": "flup", "pins": [ "h5bNchq3WpsWRNuTdNRvRaHTMBkrUFBN0WF4goIz+0Q=", "h5bN" ] }
+        ] }"#).unwrap();
+        assert_eq!(cfg.check_pins().unwrap_err(), "Invalid pin h5bN of site www.tschirky.ch, expected a base64 SHA-256 digest");
+    }
 }
@@ -0,0 +1,256 @@
+use crate::cert_retriever::SimpleCertificate;
+use crate::nagios::State;
+use crate::output::CheckResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use time::OffsetDateTime;
+
+/// Certificate last seen at an endpoint
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeenCertificate {
+    pub sha256_fingerprint: String,
+    pub serial_number: String,
+    pub issuer: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub not_before: OffsetDateTime,
+    pub spki_sha256: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub seen_at: OffsetDateTime,
+}
+
+impl SeenCertificate {
+    fn new(cert: &SimpleCertificate, seen_at: OffsetDateTime) -> SeenCertificate {
+        SeenCertificate {
+            sha256_fingerprint: cert.get_sha256_fingerprint().to_owned(),
+            serial_number: cert.get_serial_number().to_owned(),
+            issuer: cert.get_issuer().to_owned(),
+            not_before: cert.get_not_before(),
+            spki_sha256: cert.get_spki_sha256().to_owned(),
+            seen_at,
+        }
+    }
+}
+
+/// How the certificate of an endpoint differs from the one seen at the last run
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeEvent {
+    /// Replaced by a newer certificate of the same issuer
+    Renewed,
+    /// Replaced by a certificate of another issuer, e.g. by an interception appliance
+    IssuerChanged,
+    /// Replaced by a certificate issued before the last seen one, e.g. by a rollback
+    Reverted,
+    /// The public key differs from the one of the last seen certificate
+    KeyChanged,
+}
+
+impl ChangeEvent {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChangeEvent::Renewed => "renewed",
+            ChangeEvent::IssuerChanged => "replaced with different issuer",
+            ChangeEvent::Reverted => "reverted to older certificate",
+            ChangeEvent::KeyChanged => "key changed",
+        }
+    }
+
+    /// State the site is raised to, a renewal or a new key is expected from time to time
+    pub fn state(&self) -> State {
+        match self {
+            ChangeEvent::Renewed | ChangeEvent::KeyChanged => State::Ok,
+            ChangeEvent::IssuerChanged | ChangeEvent::Reverted => State::Warning,
+        }
+    }
+}
+
+/// Change of the certificate of an endpoint since the last run
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CertificateChange {
+    pub event: ChangeEvent,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StateDocument {
+    endpoints: BTreeMap<String, SeenCertificate>,
+}
+
+/// The certificates last seen at the endpoints, persisted in the state file between runs
+pub struct CertificateHistory {
+    path: PathBuf,
+    seen: BTreeMap<String, SeenCertificate>,
+}
+
+impl CertificateHistory {
+    /// The history stored at `path`, empty if the file does not exist yet
+    pub fn load(path: &Path) -> Result<CertificateHistory, String> {
+        let seen = if path.exists() {
+            let data = std::fs::read(path).map_err(|e| format!("Unable to read the state file {}: {}", path.display(), e))?;
+            serde_json::from_slice::<StateDocument>(&data)
+                .map_err(|e| format!("Invalid state file {}: {}", path.display(), e))?
+                .endpoints
+        } else {
+            BTreeMap::new()
+        };
+        Ok(CertificateHistory { path: path.to_owned(), seen })
+    }
+
+    /// Writes the history to a temporary file first, so that an interrupted run does not lose it
+    pub fn save(&self) -> Result<(), String> {
+        let document = StateDocument { endpoints: self.seen.clone() };
+        let data = serde_json::to_vec_pretty(&document).map_err(|e| e.to_string())?;
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, data)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|e| format!("Unable to write the state file {}: {}", self.path.display(), e))
+    }
+
+    /// Reports the changes of the certificate of `result` since the last run, raising its state, and remembers
+    /// the certificate for the next run. Failed checks leave the history unchanged.
+    pub fn record(&mut self, result: &mut CheckResult) {
+        let Some(cert) = &result.certificate else {
+            return;
+        };
+        let current = SeenCertificate::new(cert, result.checked_at);
+        if let Some(previous) = self.seen.get(&result.endpoint()) {
+            result.changes = changes(previous, &current);
+            result.state = result.changes.iter().map(|change| change.event.state()).fold(result.state, State::max);
+        }
+        self.seen.insert(result.endpoint(), current);
+    }
+}
+
+fn changes(previous: &SeenCertificate, current: &SeenCertificate) -> Vec<CertificateChange> {
+    let mut changes = Vec::new();
+    if previous.sha256_fingerprint == current.sha256_fingerprint {
+        return changes;
+    }
+    let mut change = |event, message| changes.push(CertificateChange { event, message });
+    let reverted = current.not_before < previous.not_before;
+    if reverted {
+        change(ChangeEvent::Reverted, format!("Serial {} is older than serial {} seen before", current.serial_number, previous.serial_number));
+    }
+    if current.issuer != previous.issuer {
+        change(ChangeEvent::IssuerChanged, format!("Issuer {} replaced by {}", previous.issuer, current.issuer));
+    } else if !reverted {
+        change(ChangeEvent::Renewed, format!("Serial {} replaced by {}", previous.serial_number, current.serial_number));
+    }
+    if current.spki_sha256 != previous.spki_sha256 {
+        change(ChangeEvent::KeyChanged, format!("Key {} replaced by {}", previous.spki_sha256, current.spki_sha256));
+    }
+    changes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_retriever::{CertError, EndpointCertificate};
+    use crate::client_auth::ClientAuth;
+    use crate::config::Site;
+    use crate::validation::ChainOrder;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    const LOCALHOST_CERT: &[u8] = include_bytes!("../testdata/pki/localhost.crt");
+    const PROFILE_CERT: &[u8] = include_bytes!("../testdata/pki/profile.crt");
+    const SELFSIGNED_CERT: &[u8] = include_bytes!("../testdata/pki/selfsigned.crt");
+
+    fn result(pem: &[u8]) -> CheckResult {
+        // without lint findings, which raise the state of the test certificates valid for decades
+        let site = Site { disabled_lints: crate::lint::rule_ids().map(str::to_owned).collect(), ..Site::new("localhost", "other", 443) };
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(pem).unwrap()).unwrap();
+        CheckResult::new(&site, Ok(EndpointCertificate {
+            certificate: cert.clone(),
+            chain: vec![cert],
+            chain_order: ChainOrder::Ordered,
+            validation: None,
+            client_auth: ClientAuth::default(),
+        }))
+    }
+
+    fn events(result: &CheckResult) -> Vec<ChangeEvent> {
+        result.changes.iter().map(|change| change.event).collect()
+    }
+
+    fn seen(pem: &[u8]) -> SeenCertificate {
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(pem).unwrap()).unwrap();
+        SeenCertificate::new(&cert, OffsetDateTime::now_utc())
+    }
+
+    #[test]
+    fn test_changes() {
+        let localhost = seen(LOCALHOST_CERT);
+        assert!(changes(&localhost, &localhost).is_empty());
+
+        // the profile certificate was issued after the localhost certificate by the same intermediate
+        let profile = seen(PROFILE_CERT);
+        let events = |previous, current| changes(previous, current).into_iter().map(|change| change.event).collect::<Vec<_>>();
+        assert_eq!(events(&localhost, &profile), [ChangeEvent::Renewed, ChangeEvent::KeyChanged]);
+        assert_eq!(events(&profile, &localhost), [ChangeEvent::Reverted, ChangeEvent::KeyChanged]);
+
+        let renewed_same_key = SeenCertificate { sha256_fingerprint: "00".to_owned(), serial_number: "01".to_owned(), ..localhost.clone() };
+        let change = changes(&localhost, &renewed_same_key);
+        assert_eq!(change, [CertificateChange {
+            event: ChangeEvent::Renewed,
+            message: format!("Serial {} replaced by 01", localhost.serial_number),
+        }]);
+
+        let intercepted = SeenCertificate { sha256_fingerprint: "00".to_owned(), issuer: "CN=Proxy CA".to_owned(), ..localhost.clone() };
+        assert_eq!(events(&localhost, &intercepted), [ChangeEvent::IssuerChanged]);
+    }
+
+    #[test]
+    fn test_record() {
+        let path = std::env::temp_dir().join(format!("cert-monitor-history-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut history = CertificateHistory::load(&path).unwrap();
+        let mut first = result(LOCALHOST_CERT);
+        history.record(&mut first);
+        assert!(first.changes.is_empty());
+        assert_eq!(first.state, State::Ok);
+        history.save().unwrap();
+
+        // the next run reads the certificate seen by the previous one
+        let mut history = CertificateHistory::load(&path).unwrap();
+        let mut same = result(LOCALHOST_CERT);
+        history.record(&mut same);
+        assert!(same.changes.is_empty());
+
+        let mut replaced = result(SELFSIGNED_CERT);
+        history.record(&mut replaced);
+        assert_eq!(events(&replaced), [ChangeEvent::IssuerChanged, ChangeEvent::KeyChanged]);
+        assert_eq!(replaced.state, State::Warning);
+
+        let mut failed = CheckResult::new(&Site::new("localhost", "other", 443),
+                                          Err(CertError::TargetNotReachable("Target localhost:443 is unreachable".to_owned())));
+        history.record(&mut failed);
+        assert!(failed.changes.is_empty());
+
+        // back to a certificate of the intermediate CA
+        let mut issuer_changed = result(PROFILE_CERT);
+        history.record(&mut issuer_changed);
+        assert_eq!(events(&issuer_changed), [ChangeEvent::IssuerChanged, ChangeEvent::KeyChanged]);
+
+        let mut reverted = result(LOCALHOST_CERT);
+        history.record(&mut reverted);
+        assert_eq!(events(&reverted), [ChangeEvent::Reverted, ChangeEvent::KeyChanged]);
+        assert_eq!(reverted.state, State::Warning);
+
+        // the profile certificate was issued after the localhost certificate by the same intermediate
+        let mut renewed = result(PROFILE_CERT);
+        history.record(&mut renewed);
+        assert_eq!(events(&renewed), [ChangeEvent::Renewed, ChangeEvent::KeyChanged]);
+        assert_eq!(renewed.state, State::Ok);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(CertificateHistory::load(&path).err().unwrap().starts_with("Invalid state file"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
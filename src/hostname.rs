@@ -0,0 +1,113 @@
+use std::net::IpAddr;
+
+/// Name of a target as it is compared against the identifiers of a certificate (RFC 6125 reference identifier)
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceIdentity {
+    /// Lower case A-label form without trailing dot
+    Dns(String),
+    Ip(IpAddr),
+}
+
+impl ReferenceIdentity {
+    /// Parses a target host, which is either an IP address, optionally in brackets, or a possibly
+    /// internationalized domain name
+    pub fn parse(target: &str) -> Result<ReferenceIdentity, String> {
+        let unbracketed = target.strip_prefix('[').and_then(|t| t.strip_suffix(']')).unwrap_or(target);
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return Ok(ReferenceIdentity::Ip(ip));
+        }
+        let name = target.strip_suffix('.').unwrap_or(target);
+        if name.is_empty() || name.contains('*') {
+            return Err(format!("{} is not a valid host name", target));
+        }
+        idna::domain_to_ascii(name)
+            .map(ReferenceIdentity::Dns)
+            .map_err(|_| format!("{} is not a valid host name", target))
+    }
+
+    /// Whether a certificate with the given subject alternative names and common name is valid for this identity.
+    /// The common name is only considered if there are no subject alternative names at all.
+    pub fn matches(&self, dns_names: &[String], ip_addresses: &[IpAddr], common_name: &str) -> bool {
+        match self {
+            ReferenceIdentity::Ip(ip) => ip_addresses.contains(ip),
+            ReferenceIdentity::Dns(reference) => {
+                if dns_names.is_empty() && ip_addresses.is_empty() {
+                    matches_dns_name(reference, common_name)
+                } else {
+                    dns_names.iter().any(|name| matches_dns_name(reference, name))
+                }
+            }
+        }
+    }
+}
+
+/// Brings a name presented in a certificate into the form of a [ReferenceIdentity::Dns]
+fn normalize_presented(name: &str) -> Option<String> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() {
+        return None;
+    }
+    if name.is_ascii() {
+        Some(name.to_ascii_lowercase())
+    } else {
+        // not allowed by RFC 5280, but seen in the common name of old certificates
+        idna::domain_to_ascii(name).ok()
+    }
+}
+
+/// Compares a normalized reference name with a presented DNS name, which may contain a wildcard
+/// as complete left-most label. The wildcard matches exactly one label and is not accepted directly
+/// below a top level domain.
+fn matches_dns_name(reference: &str, presented: &str) -> bool {
+    let Some(presented) = normalize_presented(presented) else {
+        return false;
+    };
+    match presented.strip_prefix("*.") {
+        Some(suffix) => {
+            if suffix.contains('*') || !suffix.contains('.') {
+                return false;
+            }
+            match reference.split_once('.') {
+                Some((label, rest)) => !label.is_empty() && rest == suffix,
+                None => false,
+            }
+        }
+        None => !presented.contains('*') && presented == reference,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns(name: &str) -> ReferenceIdentity {
+        ReferenceIdentity::parse(name).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(dns("WWW.Example.COM."), ReferenceIdentity::Dns("www.example.com".to_owned()));
+        assert_eq!(dns("bücher.example"), ReferenceIdentity::Dns("xn--bcher-kva.example".to_owned()));
+        assert_eq!(dns("127.0.0.1"), ReferenceIdentity::Ip("127.0.0.1".parse().unwrap()));
+        assert_eq!(dns("[::1]"), ReferenceIdentity::Ip("::1".parse().unwrap()));
+        assert_eq!(dns("2001:db8::1"), ReferenceIdentity::Ip("2001:db8::1".parse().unwrap()));
+        assert!(ReferenceIdentity::parse("").is_err());
+        assert!(ReferenceIdentity::parse("*.example.com").is_err());
+    }
+
+    #[test]
+    fn test_matches_dns_name() {
+        assert!(matches_dns_name("www.example.com", "www.example.com"));
+        assert!(matches_dns_name("www.example.com", "WWW.EXAMPLE.COM."));
+        assert!(matches_dns_name("www.example.com", "*.example.com"));
+        assert!(matches_dns_name("www.example.com", "*.Example.com."));
+        assert!(!matches_dns_name("example.com", "*.example.com"));
+        assert!(!matches_dns_name("a.www.example.com", "*.example.com"));
+        assert!(!matches_dns_name("www.example.com", "w*.example.com"));
+        assert!(!matches_dns_name("www.example.com", "*.*.com"));
+        assert!(!matches_dns_name("example.com", "*.com"));
+        assert!(!matches_dns_name("www.example.com", "www.example.org"));
+        assert!(!matches_dns_name("www.example.com", ""));
+    }
+}
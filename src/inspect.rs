@@ -0,0 +1,236 @@
+use crate::cert_retriever::{CertError, EndpointCertificate, SimpleCertificate};
+use crate::client_auth::ClientAuth;
+use crate::config::{Password, Site};
+use crate::keystore::{read_keystore, KeystoreFormat};
+use crate::validation::{check_chain_order, ChainOrder};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files read from a directory
+const CERTIFICATE_EXTENSIONS: &[&str] = &["pem", "crt", "cer", "der", "p12", "pfx", "jks", "jceks"];
+
+/// Outcome of the inspection of a single certificate or chain
+pub struct FileOutcome {
+    /// The file it was read from
+    pub file: PathBuf,
+    /// Alias of the entry if the file is a keystore
+    pub alias: Option<String>,
+    pub result: Result<EndpointCertificate, CertError>,
+}
+
+impl FileOutcome {
+    fn error(file: &Path, e: CertError) -> FileOutcome {
+        FileOutcome { file: file.to_owned(), alias: None, result: Err(e) }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, CertError> {
+    std::fs::read(path).map_err(|e| CertError::FileUnreadable(format!("Unable to read {}: {}", path.display(), e)))
+}
+
+/// All certificates of a PEM file, PEM bundle or DER file. Other PEM sections, e.g. private keys,
+/// are ignored.
+fn parse_certificates(path: &Path, data: &[u8]) -> Result<Vec<CertificateDer<'static>>, CertError> {
+    if data.windows(11).any(|window| window == b"-----BEGIN ") {
+        CertificateDer::pem_slice_iter(data)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| CertError::InvalidFormat(format!("{} is no valid PEM file: {}", path.display(), e)))
+    } else {
+        Ok(vec![CertificateDer::from(data.to_vec())])
+    }
+}
+
+/// Inspects the certificate file of a `file` site or all certificate files of its directory
+pub fn inspect_site(site: &Site) -> Vec<FileOutcome> {
+    match &site.path {
+        Some(path) => inspect_path(path, site.keystore_password.as_ref()),
+        None => vec![FileOutcome::error(Path::new(&site.target_fqn),
+                     CertError::FileUnreadable(format!("Site {} of type file has no path", site.target_fqn)))],
+    }
+}
+
+/// Inspects the file at `path` or the `*.pem`, `*.crt`, `*.cer`, `*.der`, `*.p12`, `*.pfx`, `*.jks` and
+/// `*.jceks` files of the directory at `path`, opening keystores with `password`. Files of a directory
+/// without any certificate, e.g. private keys, are skipped.
+pub fn inspect_path(path: &Path, password: Option<&Password>) -> Vec<FileOutcome> {
+    if !path.is_dir() {
+        return match inspect_file(path, password) {
+            Ok(outcomes) if outcomes.is_empty() => {
+                vec![FileOutcome::error(path, CertError::InvalidFormat(format!("{} contains no certificate", path.display())))]
+            }
+            Ok(outcomes) => outcomes,
+            Err(e) => vec![FileOutcome::error(path, e)],
+        };
+    }
+
+    let files = match certificate_files(path) {
+        Ok(files) => files,
+        Err(e) => return vec![FileOutcome::error(path, e)],
+    };
+    let mut results = Vec::new();
+    for file in files {
+        match inspect_file(&file, password) {
+            Ok(outcomes) => results.extend(outcomes),
+            Err(e) => results.push(FileOutcome::error(&file, e)),
+        }
+    }
+    if results.is_empty() {
+        results.push(FileOutcome::error(path, CertError::InvalidFormat(format!("No certificate found in {}", path.display()))));
+    }
+    results
+}
+
+/// One outcome per keystore entry with a certificate or per certificate or chain of other files
+fn inspect_file(path: &Path, password: Option<&Password>) -> Result<Vec<FileOutcome>, CertError> {
+    let data = read_file(path)?;
+    let Some(format) = KeystoreFormat::detect(path, &data) else {
+        return Ok(outcomes(path, parse_certificates(path, &data)?));
+    };
+    let password = password
+        .map(|password| password.resolve().map_err(CertError::KeystoreFailed))
+        .transpose()?;
+    let entries = read_keystore(format, &data, password.as_deref())
+        .map_err(|e| CertError::KeystoreFailed(format!("{} keystore {}: {}", format.label(), path.display(), e)))?;
+    Ok(entries.into_iter()
+        .filter(|entry| !entry.chain.is_empty())
+        .map(|entry| FileOutcome {
+            file: path.to_owned(),
+            alias: Some(entry.alias),
+            result: endpoint_certificate(&entry.chain),
+        })
+        .collect())
+}
+
+fn certificate_files(path: &Path) -> Result<Vec<PathBuf>, CertError> {
+    let entries = std::fs::read_dir(path)
+        .map_err(|e| CertError::FileUnreadable(format!("Unable to read directory {}: {}", path.display(), e)))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| CERTIFICATE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// A file whose certificates form a chain, each one issued by the next as in a `fullchain.pem`, is
+/// inspected as a whole. Any other bundle, e.g. of CA certificates, yields one outcome per certificate.
+fn outcomes(path: &Path, certs: Vec<CertificateDer<'static>>) -> Vec<FileOutcome> {
+    let outcome = |chain: &[CertificateDer]| FileOutcome { file: path.to_owned(), alias: None, result: endpoint_certificate(chain) };
+    if certs.is_empty() {
+        Vec::new()
+    } else if certs.len() > 1 && check_chain_order(&certs) != ChainOrder::Ordered {
+        certs.iter().map(|cert| outcome(std::slice::from_ref(cert))).collect()
+    } else {
+        vec![outcome(&certs)]
+    }
+}
+
+fn endpoint_certificate(certs: &[CertificateDer]) -> Result<EndpointCertificate, CertError> {
+    let chain = certs.iter()
+        .map(SimpleCertificate::from_certificate_der)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(EndpointCertificate {
+        certificate: chain[0].clone(),
+        chain,
+        chain_order: ChainOrder::Ordered,
+        validation: None,
+        client_auth: ClientAuth::default(),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// File name, alias, common name and chain length of each outcome
+    fn summary(outcomes: &[FileOutcome]) -> Vec<(String, Option<&str>, &str, usize)> {
+        outcomes.iter()
+            .map(|outcome| {
+                let cert = outcome.result.as_ref().unwrap();
+                (outcome.file.file_name().unwrap().to_string_lossy().into_owned(), outcome.alias.as_deref(),
+                 cert.certificate.get_common_name(), cert.chain.len())
+            })
+            .collect()
+    }
+
+    fn read_certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, CertError> {
+        parse_certificates(Path::new(path), &read_file(Path::new(path))?)
+    }
+
+    fn error_kind(outcomes: &[FileOutcome]) -> &'static str {
+        outcomes[0].result.as_ref().err().unwrap().kind()
+    }
+
+    #[test]
+    fn test_read_certificates() {
+        assert_eq!(read_certificates("testdata/pki/files/localhost.der").unwrap().len(), 1);
+        assert_eq!(read_certificates("testdata/pki/files/fullchain.pem").unwrap().len(), 2);
+        assert!(read_certificates("testdata/pki/files/localhost.key.pem").unwrap().is_empty());
+        let e = read_certificates("testdata/pki/files/missing.pem").err().unwrap();
+        assert_eq!(e.kind(), "FileUnreadable");
+    }
+
+    #[test]
+    fn test_inspect_file() {
+        let outcomes = inspect_path(Path::new("testdata/pki/files/fullchain.pem"), None);
+        assert_eq!(summary(&outcomes), [("fullchain.pem".to_owned(), None, "localhost", 2)]);
+
+        let outcomes = inspect_path(Path::new("testdata/pki/files/bundle.pem"), None);
+        assert_eq!(summary(&outcomes), [
+            ("bundle.pem".to_owned(), None, "Cert Monitor Test Root CA", 1),
+            ("bundle.pem".to_owned(), None, "selfsigned.test.cert-monitor.local", 1),
+        ]);
+
+        assert_eq!(error_kind(&inspect_path(Path::new("testdata/pki/files/localhost.key.pem"), None)), "InvalidFormat");
+        // neither PEM nor a certificate
+        assert_eq!(error_kind(&inspect_path(Path::new("testdata/pki/ext.cnf"), None)), "InvalidFormat");
+    }
+
+    #[test]
+    fn test_inspect_keystore() {
+        let password = Password::Value("changeit".to_owned());
+        let outcomes = inspect_path(Path::new("testdata/pki/keystores/server.jks"), Some(&password));
+        assert_eq!(summary(&outcomes), [
+            ("server.jks".to_owned(), Some("root"), "Cert Monitor Test Root CA", 1),
+            ("server.jks".to_owned(), Some("server"), "localhost", 3),
+        ]);
+
+        let wrong = Password::Value("wrong".to_owned());
+        assert_eq!(error_kind(&inspect_path(Path::new("testdata/pki/keystores/server.p12"), Some(&wrong))), "KeystoreFailed");
+        let unset = Password::Env("CERT_MONITOR_TEST_UNSET".to_owned());
+        assert_eq!(error_kind(&inspect_path(Path::new("testdata/pki/keystores/server.jks"), Some(&unset))), "KeystoreFailed");
+    }
+
+    #[test]
+    fn test_inspect_directory() {
+        let outcomes = inspect_path(Path::new("testdata/pki/files"), None);
+        assert_eq!(summary(&outcomes), [
+            ("bundle.pem".to_owned(), None, "Cert Monitor Test Root CA", 1),
+            ("bundle.pem".to_owned(), None, "selfsigned.test.cert-monitor.local", 1),
+            ("fullchain.pem".to_owned(), None, "localhost", 2),
+            ("localhost.der".to_owned(), None, "localhost", 1),
+        ]);
+
+        let password = Password::File(PathBuf::from("testdata/pki/keystores/password.txt"));
+        let outcomes = inspect_path(Path::new("testdata/pki/keystores"), Some(&password));
+        assert_eq!(summary(&outcomes).iter().map(|(file, alias, _, _)| format!("{} {}", file, alias.unwrap())).collect::<Vec<_>>(),
+                   ["server.jceks root", "server.jceks server", "server.jks root", "server.jks server", "server.p12 root", "server.p12 server"]);
+
+        let outcomes = inspect_path(Path::new("testdata/pki/client"), Some(&password));
+        assert_eq!(summary(&outcomes), [
+            ("client.crt".to_owned(), None, "monitor", 1),
+            ("client.p12".to_owned(), Some("O=Cert Monitor Test, CN=monitor"), "monitor", 2),
+        ]);
+    }
+
+    #[test]
+    fn test_inspect_site() {
+        let site = Site { path: None, ..Site::file(Path::new("/etc/nginx/ssl"), "nginx") };
+        assert_eq!(error_kind(&inspect_site(&site)), "FileUnreadable");
+    }
+}
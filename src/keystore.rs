@@ -0,0 +1,468 @@
+use rustls::pki_types::CertificateDer;
+use sha1::{Digest, Sha1};
+use std::path::Path;
+use std::rc::Rc;
+
+const JKS_MAGIC: u32 = 0xfeed_feed;
+const JCEKS_MAGIC: u32 = 0xcece_cece;
+
+/// Salt of the SHA-1 digest protecting the integrity of JKS and JCEKS keystores
+const INTEGRITY_SALT: &[u8] = b"Mighty Aphrodite";
+const DIGEST_LEN: usize = 20;
+
+const PRIVATE_KEY_ENTRY: u32 = 1;
+const TRUSTED_CERT_ENTRY: u32 = 2;
+const SECRET_KEY_ENTRY: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeystoreFormat {
+    Pkcs12,
+    /// Java KeyStore
+    Jks,
+    /// Java Cryptography Extension KeyStore, a JKS which may also hold secret keys
+    Jceks,
+}
+
+impl KeystoreFormat {
+    /// Format of the keystore file at `path` with the content `data`, none if it is no keystore.
+    /// PKCS#12 can't be told apart from a DER certificate by its content and is recognized by the
+    /// extension.
+    pub fn detect(path: &Path, data: &[u8]) -> Option<KeystoreFormat> {
+        match data.get(..4).map(|magic| u32::from_be_bytes([magic[0], magic[1], magic[2], magic[3]])) {
+            Some(JKS_MAGIC) => Some(KeystoreFormat::Jks),
+            Some(JCEKS_MAGIC) => Some(KeystoreFormat::Jceks),
+            _ => path.extension()
+                .and_then(|ext| ext.to_str())
+                .filter(|ext| ext.eq_ignore_ascii_case("p12") || ext.eq_ignore_ascii_case("pfx"))
+                .map(|_| KeystoreFormat::Pkcs12),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            KeystoreFormat::Pkcs12 => "PKCS#12",
+            KeystoreFormat::Jks => "JKS",
+            KeystoreFormat::Jceks => "JCEKS",
+        }
+    }
+}
+
+/// Entry of a keystore which holds certificates
+#[derive(Debug)]
+pub struct KeystoreEntry {
+    pub alias: String,
+    /// The chain of a private key entry, the single certificate of a trusted certificate entry
+    pub chain: Vec<CertificateDer<'static>>,
+}
+
+/// Reads the private key and trusted certificate entries of a keystore, sorted by alias. Secret key
+/// entries hold no certificate and are skipped. The integrity of a JKS or JCEKS keystore is only
+/// checked if a password is given, as keytool does.
+pub fn read_keystore(format: KeystoreFormat, data: &[u8], password: Option<&str>) -> Result<Vec<KeystoreEntry>, String> {
+    let mut entries = match format {
+        KeystoreFormat::Pkcs12 => read_pkcs12(data, password.unwrap_or_default())?,
+        KeystoreFormat::Jks | KeystoreFormat::Jceks => read_java_keystore(data, password)?,
+    };
+    entries.sort_by(|a, b| a.alias.cmp(&b.alias));
+    Ok(entries)
+}
+
+fn read_pkcs12(data: &[u8], password: &str) -> Result<Vec<KeystoreEntry>, String> {
+    let store = p12_keystore::KeyStore::from_pkcs12(data, password)
+        .map_err(|e| format!("Unable to open the keystore, the password may be wrong: {}", e))?;
+    Ok(store.entries()
+        .map(|(alias, entry)| {
+            let chain = match entry {
+                p12_keystore::KeyStoreEntry::PrivateKeyChain(key_chain) => key_chain.chain().iter()
+                    .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+                    .collect(),
+                p12_keystore::KeyStoreEntry::Certificate(cert) => vec![CertificateDer::from(cert.as_der().to_vec())],
+            };
+            KeystoreEntry { alias: alias.clone(), chain }
+        })
+        .collect())
+}
+
+fn read_java_keystore(data: &[u8], password: Option<&str>) -> Result<Vec<KeystoreEntry>, String> {
+    if data.len() < DIGEST_LEN {
+        return Err("The keystore is truncated".to_owned());
+    }
+    let (content, digest) = data.split_at(data.len() - DIGEST_LEN);
+    if let Some(password) = password {
+        let mut sha = Sha1::new();
+        password.encode_utf16().for_each(|unit| sha.update(unit.to_be_bytes()));
+        sha.update(INTEGRITY_SALT);
+        sha.update(content);
+        if sha.finalize().as_slice() != digest {
+            return Err("The keystore password is incorrect or the keystore is corrupted".to_owned());
+        }
+    }
+
+    let mut reader = Reader { data: content, pos: 0 };
+    let magic = reader.u32()?;
+    let version = reader.u32()?;
+    if version != 1 && version != 2 {
+        return Err(format!("Unsupported keystore version {}", version));
+    }
+    let count = reader.u32()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let tag = reader.u32()?;
+        let alias = reader.utf()?;
+        // creation date
+        reader.u64()?;
+        match tag {
+            PRIVATE_KEY_ENTRY => {
+                let key_len = reader.u32()?;
+                reader.bytes(key_len as usize)?;
+                let chain_len = reader.u32()?;
+                let chain = (0..chain_len)
+                    .map(|_| read_certificate(&mut reader, version))
+                    .collect::<Result<Vec<_>, _>>()?;
+                entries.push(KeystoreEntry { alias, chain });
+            }
+            TRUSTED_CERT_ENTRY => {
+                let chain = vec![read_certificate(&mut reader, version)?];
+                entries.push(KeystoreEntry { alias, chain });
+            }
+            SECRET_KEY_ENTRY if magic == JCEKS_MAGIC => {
+                ObjectStream::skip(&mut reader)
+                    .map_err(|e| format!("Unable to read the secret key entry {}: {}", alias, e))?;
+            }
+            _ => return Err(format!("Unsupported entry type {} of {}", tag, alias)),
+        }
+    }
+    Ok(entries)
+}
+
+fn read_certificate(reader: &mut Reader, version: u32) -> Result<CertificateDer<'static>, String> {
+    if version == 2 {
+        let cert_type = reader.utf()?;
+        if cert_type != "X.509" {
+            return Err(format!("Unsupported certificate type {}", cert_type));
+        }
+    }
+    let len = reader.u32()?;
+    Ok(CertificateDer::from(reader.bytes(len as usize)?.to_vec()))
+}
+
+/// Big endian primitives as written by Java's DataOutputStream
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.pos.checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| "The keystore is truncated".to_owned())?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn peek(&self) -> Result<u8, String> {
+        self.data.get(self.pos).copied().ok_or_else(|| "The keystore is truncated".to_owned())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+
+    /// String with a 16 bit length, modified UTF-8 is read as UTF-8
+    fn utf(&mut self) -> Result<String, String> {
+        let len = self.u16()?;
+        Ok(String::from_utf8_lossy(self.bytes(len as usize)?).into_owned())
+    }
+}
+
+const STREAM_MAGIC: u16 = 0xaced;
+const STREAM_VERSION: u16 = 5;
+const TC_NULL: u8 = 0x70;
+const TC_REFERENCE: u8 = 0x71;
+const TC_CLASSDESC: u8 = 0x72;
+const TC_OBJECT: u8 = 0x73;
+const TC_STRING: u8 = 0x74;
+const TC_ARRAY: u8 = 0x75;
+const TC_BLOCKDATA: u8 = 0x77;
+const TC_ENDBLOCKDATA: u8 = 0x78;
+const TC_BLOCKDATALONG: u8 = 0x7a;
+const TC_LONGSTRING: u8 = 0x7c;
+const TC_ENUM: u8 = 0x7e;
+const BASE_WIRE_HANDLE: u32 = 0x7e_0000;
+const SC_WRITE_METHOD: u8 = 0x01;
+const SC_EXTERNALIZABLE: u8 = 0x04;
+/// Deepest nesting of elements accepted, so that a corrupted stream can't overflow the stack
+const MAX_DEPTH: usize = 64;
+
+struct ClassDesc {
+    name: String,
+    flags: u8,
+    /// Type codes of the serialized fields
+    fields: Vec<u8>,
+    super_desc: Option<Rc<ClassDesc>>,
+}
+
+/// Skips a Java serialized object, as JCEKS stores its secret keys. Only as much of the stream
+/// is understood as needed to find its end.
+struct ObjectStream<'r, 'a> {
+    reader: &'r mut Reader<'a>,
+    /// Class descriptors by handle, none for other objects
+    handles: Vec<Option<Rc<ClassDesc>>>,
+    /// Number of elements currently being read, each one nested in the previous
+    depth: usize,
+}
+
+impl ObjectStream<'_, '_> {
+    fn skip(reader: &mut Reader) -> Result<(), String> {
+        if reader.u16()? != STREAM_MAGIC || reader.u16()? != STREAM_VERSION {
+            return Err("not a serialized object".to_owned());
+        }
+        ObjectStream { reader, handles: Vec::new(), depth: 0 }.content()?;
+        Ok(())
+    }
+
+    /// Reads one element of the stream, returns it if it is a class descriptor
+    fn content(&mut self) -> Result<Option<Rc<ClassDesc>>, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err("nesting too deep".to_owned());
+        }
+        self.depth += 1;
+        let content = self.element();
+        self.depth -= 1;
+        content
+    }
+
+    fn element(&mut self) -> Result<Option<Rc<ClassDesc>>, String> {
+        match self.reader.u8()? {
+            TC_NULL => Ok(None),
+            TC_REFERENCE => {
+                let handle = self.reader.u32()?.checked_sub(BASE_WIRE_HANDLE)
+                    .and_then(|index| self.handles.get(index as usize))
+                    .ok_or_else(|| "invalid reference".to_owned())?;
+                Ok(handle.clone())
+            }
+            TC_CLASSDESC => self.class_desc().map(Some),
+            TC_OBJECT => {
+                let desc = self.content()?.ok_or_else(|| "object without class".to_owned())?;
+                self.handles.push(None);
+                self.class_data(&desc)?;
+                Ok(None)
+            }
+            TC_STRING => {
+                self.handles.push(None);
+                self.reader.utf()?;
+                Ok(None)
+            }
+            TC_LONGSTRING => {
+                self.handles.push(None);
+                let len = self.reader.u64()?;
+                self.reader.bytes(usize::try_from(len).map_err(|_| "string too long".to_owned())?)?;
+                Ok(None)
+            }
+            TC_ARRAY => {
+                let desc = self.content()?.ok_or_else(|| "array without class".to_owned())?;
+                self.handles.push(None);
+                let size = self.reader.u32()?;
+                let element = desc.name.as_bytes().get(1).copied().unwrap_or_default();
+                match primitive_size(element) {
+                    Some(element_size) => {
+                        self.reader.bytes(size as usize * element_size)?;
+                    }
+                    None => {
+                        for _ in 0..size {
+                            self.value(element)?;
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            TC_ENUM => {
+                self.content()?;
+                self.handles.push(None);
+                self.content()?;
+                Ok(None)
+            }
+            TC_BLOCKDATA => {
+                let len = self.reader.u8()?;
+                self.reader.bytes(len as usize)?;
+                Ok(None)
+            }
+            TC_BLOCKDATALONG => {
+                let len = self.reader.u32()?;
+                self.reader.bytes(len as usize)?;
+                Ok(None)
+            }
+            tc => Err(format!("unsupported element {:#04x}", tc)),
+        }
+    }
+
+    fn class_desc(&mut self) -> Result<Rc<ClassDesc>, String> {
+        let name = self.reader.utf()?;
+        // serialVersionUID
+        self.reader.u64()?;
+        let handle = self.handles.len();
+        self.handles.push(None);
+        let flags = self.reader.u8()?;
+        let field_count = self.reader.u16()?;
+        let mut fields = Vec::with_capacity(field_count as usize);
+        for _ in 0..field_count {
+            let typecode = self.reader.u8()?;
+            self.reader.utf()?;
+            if typecode == b'L' || typecode == b'[' {
+                // class name of the field
+                self.content()?;
+            }
+            fields.push(typecode);
+        }
+        self.annotation()?;
+        let super_desc = self.content()?;
+        let desc = Rc::new(ClassDesc { name, flags, fields, super_desc });
+        self.handles[handle] = Some(desc.clone());
+        Ok(desc)
+    }
+
+    /// Elements written by a custom `writeObject`, up to the end marker
+    fn annotation(&mut self) -> Result<(), String> {
+        while self.reader.peek()? != TC_ENDBLOCKDATA {
+            self.content()?;
+        }
+        self.reader.u8()?;
+        Ok(())
+    }
+
+    /// Field values of `desc` and its super classes, the topmost first
+    fn class_data(&mut self, desc: &ClassDesc) -> Result<(), String> {
+        if let Some(super_desc) = &desc.super_desc {
+            self.class_data(super_desc)?;
+        }
+        for typecode in &desc.fields {
+            self.value(*typecode)?;
+        }
+        if desc.flags & (SC_WRITE_METHOD | SC_EXTERNALIZABLE) != 0 {
+            self.annotation()?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, typecode: u8) -> Result<(), String> {
+        match primitive_size(typecode) {
+            Some(size) => {
+                self.reader.bytes(size)?;
+            }
+            None if typecode == b'L' || typecode == b'[' => {
+                self.content()?;
+            }
+            None => return Err(format!("unsupported field type {}", typecode as char)),
+        }
+        Ok(())
+    }
+}
+
+fn primitive_size(typecode: u8) -> Option<usize> {
+    match typecode {
+        b'B' | b'Z' => Some(1),
+        b'C' | b'S' => Some(2),
+        b'I' | b'F' => Some(4),
+        b'J' | b'D' => Some(8),
+        _ => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    fn read(file: &str, password: Option<&str>) -> Result<Vec<KeystoreEntry>, String> {
+        let path = Path::new("testdata/pki/keystores").join(file);
+        let data = std::fs::read(&path).unwrap();
+        read_keystore(KeystoreFormat::detect(&path, &data).unwrap(), &data, password)
+    }
+
+    fn common_names(entries: &[KeystoreEntry]) -> Vec<(&str, Vec<String>)> {
+        entries.iter()
+            .map(|entry| (entry.alias.as_str(), entry.chain.iter()
+                .map(|cert| X509Certificate::from_der(cert.as_ref()).unwrap().1.subject().to_string())
+                .collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(KeystoreFormat::detect(Path::new("a.jks"), &[0xfe, 0xed, 0xfe, 0xed, 0]), Some(KeystoreFormat::Jks));
+        assert_eq!(KeystoreFormat::detect(Path::new("a.ks"), &[0xce, 0xce, 0xce, 0xce, 0]), Some(KeystoreFormat::Jceks));
+        assert_eq!(KeystoreFormat::detect(Path::new("a.PFX"), &[0x30, 0x82]), Some(KeystoreFormat::Pkcs12));
+        assert_eq!(KeystoreFormat::detect(Path::new("a.der"), &[0x30, 0x82]), None);
+    }
+
+    #[test]
+    fn test_read_keystores() {
+        let root = "O=Cert Monitor Test, CN=Cert Monitor Test Root CA".to_owned();
+        let intermediate = "O=Cert Monitor Test, CN=Cert Monitor Test Intermediate CA".to_owned();
+        let entries = read("server.p12", Some("changeit")).unwrap();
+        assert_eq!(common_names(&entries), [
+            ("root", vec![root.clone()]),
+            ("server", vec!["CN=localhost".to_owned(), intermediate.clone()]),
+        ]);
+        // keytool completed the chain with the trusted root on the import, the secret key of the JCEKS is skipped
+        for file in ["server.jks", "server.jceks"] {
+            let entries = read(file, Some("changeit")).unwrap();
+            assert_eq!(common_names(&entries), [
+                ("root", vec![root.clone()]),
+                ("server", vec!["CN=localhost".to_owned(), intermediate.clone(), root.clone()]),
+            ], "{}", file);
+        }
+        // integrity not checked
+        assert_eq!(read("server.jks", None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        assert!(read("server.p12", Some("wrong")).is_err());
+        assert_eq!(read("server.jks", Some("wrong")).err().unwrap(), "The keystore password is incorrect or the keystore is corrupted");
+        assert!(read("server.jceks", Some("wrong")).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = std::fs::read("testdata/pki/keystores/server.jceks").unwrap();
+        assert!(read_keystore(KeystoreFormat::Jceks, &data[..200], None).is_err());
+        assert!(read_keystore(KeystoreFormat::Jceks, &data[..10], None).is_err());
+    }
+
+    #[test]
+    fn test_nesting_too_deep() {
+        // a secret key entry with an array of arrays of arrays ... as class of its object
+        let mut data = Vec::new();
+        data.extend(JCEKS_MAGIC.to_be_bytes());
+        data.extend(2u32.to_be_bytes());
+        data.extend(1u32.to_be_bytes());
+        data.extend(SECRET_KEY_ENTRY.to_be_bytes());
+        data.extend(3u16.to_be_bytes());
+        data.extend(b"aes");
+        data.extend(0u64.to_be_bytes());
+        data.extend(STREAM_MAGIC.to_be_bytes());
+        data.extend(STREAM_VERSION.to_be_bytes());
+        data.push(TC_OBJECT);
+        data.extend([TC_ARRAY; 100_000]);
+        data.extend([0; DIGEST_LEN]);
+        assert_eq!(read_keystore(KeystoreFormat::Jceks, &data, None).err().unwrap(),
+                   "Unable to read the secret key entry aes: nesting too deep");
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cert_retriever::{get_common_name, get_san_dns_names, get_san_ip_addresses};
+use crate::nagios::State;
+use crate::profile;
+use crate::profile::PublicKeyInfo;
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use serde::Serialize;
+use std::net::IpAddr;
+use x509_parser::prelude::X509Certificate;
+
+/// Smallest RSA and DSA key in bits which is not reported as too short
+const MIN_RSA_KEY_SIZE: usize = 2048;
+/// Smallest EC key in bits which is not reported as too short
+const MIN_EC_KEY_SIZE: usize = 256;
+/// Longest validity of a publicly trusted TLS certificate according to the CA/Browser Forum baseline requirements
+const MAX_VALIDITY_DAYS: i64 = 398;
+
+/// How bad the violation of a lint rule is
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum LintSeverity {
+    /// Raises the state of the result to warning
+    Warning,
+    /// Raises the state of the result to critical
+    Error,
+}
+
+impl LintSeverity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LintSeverity::Warning => "warning",
+            LintSeverity::Error => "error",
+        }
+    }
+
+    pub fn state(&self) -> State {
+        match self {
+            LintSeverity::Warning => State::Warning,
+            LintSeverity::Error => State::Critical,
+        }
+    }
+}
+
+/// Rule of the lint catalogue
+pub struct LintRule {
+    /// Stable id to disable the rule with
+    pub id: &'static str,
+    pub severity: LintSeverity,
+    pub description: &'static str,
+    /// Whether the rule only applies to end entity certificates, not to CA certificates
+    end_entity_only: bool,
+    /// Describes the violation if the certificate violates the rule
+    check: fn(&X509Certificate) -> Option<String>,
+}
+
+/// All lint rules, applied to every certificate of the chain
+pub const RULES: &[LintRule] = &[
+    LintRule {
+        id: "weak-key",
+        severity: LintSeverity::Error,
+        description: "RSA or DSA key shorter than 2048 bits or EC key shorter than 256 bits",
+        end_entity_only: false,
+        check: check_weak_key,
+    },
+    LintRule {
+        id: "weak-signature",
+        severity: LintSeverity::Error,
+        description: "Signature based on MD2, MD5 or SHA-1, self-signed certificates excepted",
+        end_entity_only: false,
+        check: check_weak_signature,
+    },
+    LintRule {
+        id: "missing-server-auth",
+        severity: LintSeverity::Warning,
+        description: "Extended key usage without serverAuth, or none at all",
+        end_entity_only: true,
+        check: check_server_auth,
+    },
+    LintRule {
+        id: "validity-too-long",
+        severity: LintSeverity::Warning,
+        description: "Valid for more than the 398 days allowed by the CA/Browser Forum baseline requirements",
+        end_entity_only: true,
+        check: check_validity,
+    },
+    LintRule {
+        id: "missing-san",
+        severity: LintSeverity::Error,
+        description: "No subject alternative names, browsers ignore the common name",
+        end_entity_only: true,
+        check: check_san,
+    },
+    LintRule {
+        id: "cn-not-in-san",
+        severity: LintSeverity::Warning,
+        description: "Common name which is not one of the subject alternative names",
+        end_entity_only: true,
+        check: check_cn_in_san,
+    },
+];
+
+/// Violation of a lint rule by a certificate
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LintFinding {
+    /// Id of the violated rule
+    pub rule: &'static str,
+    pub severity: LintSeverity,
+    pub message: String,
+}
+
+/// Ids of all rules
+pub fn rule_ids() -> impl Iterator<Item=&'static str> {
+    RULES.iter().map(|rule| rule.id)
+}
+
+/// The first of the `ids` which is no rule of the catalogue
+pub fn unknown_rule(ids: &[String]) -> Option<&str> {
+    ids.iter().map(String::as_str).find(|id| !rule_ids().any(|rule| rule == *id))
+}
+
+/// Parser of a rule id on the command line, listing the whole catalogue in the help
+pub fn rule_parser() -> PossibleValuesParser {
+    PossibleValuesParser::new(RULES.iter().map(|rule| PossibleValue::new(rule.id).help(rule.description)))
+}
+
+/// Findings of all rules which apply to the certificate
+pub fn lint(cert: &X509Certificate) -> Vec<LintFinding> {
+    RULES.iter()
+        .filter(|rule| !(rule.end_entity_only && cert.is_ca()))
+        .filter_map(|rule| (rule.check)(cert).map(|message| LintFinding { rule: rule.id, severity: rule.severity, message }))
+        .collect()
+}
+
+/// Whether the algorithm relies on MD2, MD5 or SHA-1, which allow forging signatures
+pub fn is_weak_signature(algorithm: &str) -> bool {
+    let algorithm = algorithm.to_ascii_lowercase();
+    ["md2", "md5", "sha1"].iter().any(|hash| algorithm.contains(hash))
+}
+
+/// Whether the key is shorter than the current recommendations for its algorithm
+pub fn is_short_key(key: &PublicKeyInfo) -> bool {
+    match (key.algorithm.as_str(), key.size) {
+        ("RSA" | "RSASSA-PSS" | "DSA", Some(size)) => size < MIN_RSA_KEY_SIZE,
+        ("EC", Some(size)) => size < MIN_EC_KEY_SIZE,
+        _ => false,
+    }
+}
+
+fn check_weak_key(cert: &X509Certificate) -> Option<String> {
+    let key = profile::get_public_key(cert);
+    is_short_key(&key).then(|| format!("{} key of {} bits", key.algorithm, key.size.unwrap_or_default()))
+}
+
+fn check_weak_signature(cert: &X509Certificate) -> Option<String> {
+    // nobody verifies the signature of a self-signed certificate, it is trusted as is
+    if cert.subject() == cert.issuer() {
+        return None;
+    }
+    let algorithm = profile::get_signature_algorithm(cert);
+    is_weak_signature(&algorithm).then(|| format!("Signed with {}", algorithm))
+}
+
+fn check_server_auth(cert: &X509Certificate) -> Option<String> {
+    match cert.extended_key_usage() {
+        Ok(Some(eku)) if eku.value.server_auth || eku.value.any => None,
+        Ok(Some(_)) => Some(format!("Extended key usage {} without serverAuth", profile::get_extended_key_usage(cert).join(", "))),
+        _ => Some("No extended key usage".to_owned()),
+    }
+}
+
+fn check_validity(cert: &X509Certificate) -> Option<String> {
+    let validity = cert.validity();
+    let days = (validity.not_after.timestamp() - validity.not_before.timestamp()) / 86400;
+    (days > MAX_VALIDITY_DAYS).then(|| format!("Valid for {} days", days))
+}
+
+fn check_san(cert: &X509Certificate) -> Option<String> {
+    (get_san_dns_names(cert).is_empty() && get_san_ip_addresses(cert).is_empty())
+        .then(|| "No subject alternative names".to_owned())
+}
+
+fn check_cn_in_san(cert: &X509Certificate) -> Option<String> {
+    let common_name = get_common_name(cert);
+    let dns_names = get_san_dns_names(cert);
+    let ip_addresses = get_san_ip_addresses(cert);
+    // a certificate without any is reported by missing-san
+    if common_name.is_empty() || (dns_names.is_empty() && ip_addresses.is_empty()) {
+        return None;
+    }
+    let listed = dns_names.iter().any(|name| name.eq_ignore_ascii_case(&common_name))
+        || common_name.parse::<IpAddr>().is_ok_and(|ip| ip_addresses.contains(&ip));
+    (!listed).then(|| format!("Common name {} is not a subject alternative name", common_name))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+    use x509_parser::prelude::FromDer;
+
+    const WEAK_CERT: &[u8] = include_bytes!("../testdata/pki/lint/weak.crt");
+    const LEGACY_CERT: &[u8] = include_bytes!("../testdata/pki/legacy.crt");
+    const ROOT_CERT: &[u8] = include_bytes!("../testdata/pki/root.crt");
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+
+    /// Rule ids and messages of the findings of the PEM or DER certificate
+    fn findings(data: &[u8]) -> Vec<(&'static str, String)> {
+        let der = CertificateDer::from_pem_slice(data).unwrap_or_else(|_| CertificateDer::from(data));
+        let (_, cert) = X509Certificate::from_der(der.as_ref()).unwrap();
+        lint(&cert).into_iter().map(|finding| (finding.rule, finding.message)).collect()
+    }
+
+    #[test]
+    fn test_weak_certificate() {
+        assert_eq!(findings(WEAK_CERT), [
+            ("weak-key", "RSA key of 1024 bits".to_owned()),
+            ("weak-signature", "Signed with ecdsa-with-SHA1".to_owned()),
+            ("missing-server-auth", "No extended key usage".to_owned()),
+            ("validity-too-long", "Valid for 825 days".to_owned()),
+            ("cn-not-in-san", "Common name weak.test.cert-monitor.local is not a subject alternative name".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_end_entity_rules() {
+        assert_eq!(findings(LEGACY_CERT), [
+            ("validity-too-long", "Valid for 35000 days".to_owned()),
+            ("missing-san", "No subject alternative names".to_owned()),
+        ]);
+        // a root CA is neither a server nor limited to 398 days
+        assert!(findings(ROOT_CERT).is_empty());
+        assert!(findings(GITEA_CERT).is_empty());
+    }
+
+    #[test]
+    fn test_catalogue() {
+        let ids: Vec<&str> = rule_ids().collect();
+        assert_eq!(ids.len(), RULES.len());
+        assert!(ids.iter().enumerate().all(|(index, id)| !ids[index + 1..].contains(id)));
+        assert_eq!(unknown_rule(&["weak-key".to_owned(), "weak-keys".to_owned()]), Some("weak-keys"));
+        assert_eq!(unknown_rule(&["cn-not-in-san".to_owned()]), None);
+        let key = |algorithm: &str, size| PublicKeyInfo { algorithm: algorithm.to_owned(), size: Some(size), curve: None };
+        assert!(is_short_key(&key("RSA", 1024)));
+        assert!(!is_short_key(&key("RSA", 2048)));
+        assert!(is_short_key(&key("EC", 192)));
+        assert!(!is_short_key(&key("Ed25519", 255)));
+        assert!(is_weak_signature("md5WithRSAEncryption"));
+        assert!(is_weak_signature("ecdsa-with-SHA1"));
+        assert!(!is_weak_signature("sha256WithRSAEncryption"));
+        assert_eq!(LintSeverity::Error.state(), State::Critical);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::config::LogSinkConfig;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+/// Facility `daemon` as defined in RFC 5424
+const SYSLOG_FACILITY_DAEMON: u8 = 3;
+
+/// SD-ID of the structured data element, uses the private enterprise number reserved for documentation
+const SYSLOG_SD_ID: &str = "cert@32473";
+
+/// Installs the logger writing to the given sink as the global `log` backend
+pub fn init(sink_config: &LogSinkConfig) -> io::Result<()> {
+    let sink = Sink::open(sink_config)?;
+    log::set_boxed_logger(Box::new(SinkLogger { sink: Mutex::new(sink) }))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    log::set_max_level(LevelFilter::Info);
+    Ok(())
+}
+
+struct SinkLogger {
+    sink: Mutex<Sink>,
+}
+
+enum Sink {
+    Stderr,
+    File(RotatingFile),
+    #[cfg(unix)]
+    Syslog(UnixDatagram),
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+}
+
+impl Sink {
+    fn open(sink_config: &LogSinkConfig) -> io::Result<Sink> {
+        match sink_config {
+            LogSinkConfig::Stderr => Ok(Sink::Stderr),
+            LogSinkConfig::File { path, max_size_bytes, max_files } => {
+                Ok(Sink::File(RotatingFile::open(path, *max_size_bytes, *max_files)?))
+            }
+            #[cfg(unix)]
+            LogSinkConfig::Syslog { socket } => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(socket)?;
+                Ok(Sink::Syslog(sock))
+            }
+            #[cfg(unix)]
+            LogSinkConfig::Journald { socket } => {
+                let sock = UnixDatagram::unbound()?;
+                sock.connect(socket)?;
+                Ok(Sink::Journald(sock))
+            }
+            #[cfg(not(unix))]
+            LogSinkConfig::Syslog { .. } | LogSinkConfig::Journald { .. } => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "syslog and journald sinks need unix domain sockets"))
+            }
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self {
+            Sink::Stderr => {
+                writeln!(io::stderr(), "{}", format_line(record))
+            }
+            Sink::File(file) => {
+                file.write_line(&format_line(record))
+            }
+            #[cfg(unix)]
+            Sink::Syslog(sock) => {
+                sock.send(format_syslog(record, &hostname()).as_bytes()).map(|_| ())
+            }
+            #[cfg(unix)]
+            Sink::Journald(sock) => {
+                sock.send(&format_journald(record)).map(|_| ())
+            }
+        }
+    }
+}
+
+impl Log for SinkLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Ok(mut sink) = self.sink.lock() {
+            if let Err(e) = sink.write(record) {
+                eprintln!("Unable to write log record: {}", e);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            if let Sink::File(file) = &mut *sink {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+/// Collects the key value pairs of a record in the order they were given
+struct FieldCollector(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+fn fields(record: &Record) -> Vec<(String, String)> {
+    let mut collector = FieldCollector(Vec::new());
+    let _ = record.key_values().visit(&mut collector);
+    collector.0
+}
+
+fn timestamp() -> String {
+    OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_else(|_| "-".to_owned())
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|name| name.trim().to_owned())
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "-".to_owned())
+}
+
+/// `<timestamp> <LEVEL> <target>: <message> key=value ...` as used for stderr and files
+fn format_line(record: &Record) -> String {
+    let mut line = format!("{} {} {}: {}", timestamp(), record.level(), record.target(), record.args());
+    fields(record).iter().for_each(|(key, value)| {
+        if value.contains(char::is_whitespace) || value.is_empty() {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    });
+    line
+}
+
+/// RFC 5424 message with the record fields as structured data
+fn format_syslog(record: &Record, hostname: &str) -> String {
+    let pri = SYSLOG_FACILITY_DAEMON * 8 + severity(record.level());
+    let app_name: String = record.target().chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(48)
+        .collect();
+    let app_name = if app_name.is_empty() { "-".to_owned() } else { app_name };
+
+    let fields = fields(record);
+    let structured_data = if fields.is_empty() {
+        "-".to_owned()
+    } else {
+        let mut sd = format!("[{}", SYSLOG_SD_ID);
+        fields.iter().for_each(|(key, value)| {
+            let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]");
+            sd.push_str(&format!(" {}=\"{}\"", key, escaped));
+        });
+        sd.push(']');
+        sd
+    };
+
+    format!("<{}>1 {} {} {} {} - {} {}", pri, timestamp(), hostname, app_name, std::process::id(), structured_data, record.args())
+}
+
+/// Datagram in the native journald protocol, field names are upper cased
+fn format_journald(record: &Record) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    let mut append = |key: &str, value: &str| {
+        if value.contains('\n') {
+            datagram.extend_from_slice(key.as_bytes());
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            datagram.extend_from_slice(value.as_bytes());
+        } else {
+            datagram.extend_from_slice(format!("{}={}", key, value).as_bytes());
+        }
+        datagram.push(b'\n');
+    };
+
+    append("MESSAGE", &record.args().to_string());
+    append("PRIORITY", &severity(record.level()).to_string());
+    append("SYSLOG_IDENTIFIER", record.target());
+    fields(record).iter().for_each(|(key, value)| {
+        let key: String = key.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        append(key.trim_start_matches('_'), value);
+    });
+    datagram
+}
+
+/// Log file which is moved to `<path>.1` (and older files shifted up to `<path>.<max_files>`)
+/// before it would grow over `max_size` bytes
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: u32,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: u32) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_record<F>(level: Level, f: F) where F: FnOnce(&Record) {
+        let kvs: &[(&str, &str)] = &[("host", "www.tschirky.ch"), ("port", "443"), ("cn", "my \"quoted\" ]name")];
+        f(&Record::builder()
+            .args(format_args!("certificate checked"))
+            .level(level)
+            .target("monitor")
+            .key_values(&kvs)
+            .build());
+    }
+
+    #[test]
+    fn test_format_line() {
+        with_record(Level::Info, |record| {
+            let line = format_line(record);
+            assert!(line.ends_with(" INFO monitor: certificate checked host=www.tschirky.ch port=443 cn=\"my \\\"quoted\\\" ]name\""), "{}", line);
+        });
+    }
+
+    #[test]
+    fn test_format_syslog() {
+        with_record(Level::Warn, |record| {
+            let msg = format_syslog(record, "myhost");
+            assert!(msg.starts_with("<28>1 "), "{}", msg);
+            let expected = format!(" myhost monitor {} - [cert@32473 host=\"www.tschirky.ch\" port=\"443\" cn=\"my \\\"quoted\\\" \\]name\"] certificate checked", std::process::id());
+            assert!(msg.ends_with(&expected), "{}", msg);
+        });
+    }
+
+    #[test]
+    fn test_format_journald() {
+        with_record(Level::Error, |record| {
+            let datagram = String::from_utf8(format_journald(record)).unwrap();
+            assert_eq!(datagram, "MESSAGE=certificate checked\nPRIORITY=3\nSYSLOG_IDENTIFIER=monitor\nHOST=www.tschirky.ch\nPORT=443\nCN=my \"quoted\" ]name\n");
+        });
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("cert-monitor-log-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("monitor.log");
+
+        let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(std::fs::read_to_string(dir.join("monitor.log.1")).unwrap(), "third line\n");
+        assert_eq!(std::fs::read_to_string(dir.join("monitor.log.2")).unwrap(), "second line\n");
+        assert!(!dir.join("monitor.log.3").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
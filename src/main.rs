@@ -1,11 +1,42 @@
 mod cert_retriever;
+mod client_auth;
 mod config;
-use crate::cert_retriever::{CertError, CertRetriever};
-use crate::config::{load_config_file, Site, SiteConfig};
+mod history;
+mod hostname;
+mod inspect;
+mod keystore;
+mod lint;
+mod logger;
+mod nagios;
+mod output;
+mod policy;
+mod pool;
+mod profile;
+mod proxy;
+mod scheduler;
+mod severity;
+mod starttls;
+mod text;
+mod trust;
+mod validation;
+use crate::cert_retriever::CertError;
+use crate::config::{load_config_file, ClientCertificateConfig, Password, Site, SiteConfig, SiteType, TrustStoreConfig};
+use crate::history::CertificateHistory;
+use crate::inspect::FileOutcome;
+use crate::nagios::State;
+use crate::output::{create_writer, CertField, CheckResult, OutputFormat, ResultWriter};
+use crate::policy::Policy;
+use crate::scheduler::Scheduler;
+use crate::proxy::ProxySetting;
+use crate::starttls::StartTls;
+use crate::trust::Retrievers;
 use clap::{Parser, Subcommand};
-use console::{style, Style};
+use std::net::IpAddr;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 use std::path::PathBuf;
 use std::process::ExitCode;
+use std::sync::mpsc;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None, version = env!("CARGO_PKG_VERSION"))]
@@ -15,6 +46,8 @@ struct Cli {
 }
 
 #[derive(Subcommand)]
+// parsed once at startup, the size does not matter
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Installs the Windows service
     InstallService {},
@@ -25,8 +58,8 @@ enum Commands {
         #[arg(short = 'c', long, value_name = "FILE")]
         config_file: PathBuf,
 
-        /// monitor interval
-        #[arg(short = 'i', long, value_name = "interval")]
+        /// Monitor interval in hours. Keeps running and re-checks the sites until terminated
+        #[arg(short = 'i', long, value_name = "interval", value_parser = clap::value_parser!(u32).range(1..))]
         interval_hours: Option<u32>,
 
         /// Daemon mode without verbose console output but log entries instead
@@ -35,7 +68,27 @@ enum Commands {
 
         /// Output the certificate instead of the table
         #[arg(short = 'o', long, value_name = "cert", default_value = "false")]
-        cert_output: bool
+        cert_output: bool,
+
+        /// Output the whole chain presented by the server as PEM bundle, implies --cert-output
+        #[arg(long, default_value = "false")]
+        full_chain: bool,
+
+        /// Format of the console output
+        #[arg(short = 'f', long, value_name = "format", value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+
+        /// Certificate fields of the table, csv and json output, e.g. `cn,issuer,not-after,sha256`
+        #[arg(long, value_name = "field", value_enum, value_delimiter = ',')]
+        fields: Vec<CertField>,
+
+        /// Number of sites checked at the same time, overrides `workers` of the config file
+        #[arg(short = 'w', long, value_name = "count", value_parser = clap::value_parser!(u32).range(1..))]
+        workers: Option<u32>,
+
+        /// JSON file remembering the certificates of the last run, overrides `stateFile` of the config file
+        #[arg(long, value_name = "FILE")]
+        state_file: Option<PathBuf>,
     },
     
     /// Checks the target given on the command line
@@ -48,11 +101,145 @@ enum Commands {
         #[arg(short = 'p', long, value_name = "target_port", default_value = "443")]
         target_port: u32,
 
+        /// Remaining days below which the certificate is reported as warning
+        #[arg(short = 'w', long, value_name = "days", default_value = "15")]
+        warning_days: i64,
+
+        /// Remaining days below which the certificate is reported as critical
+        #[arg(short = 'c', long, value_name = "days", default_value = "7")]
+        critical_days: i64,
+
+        /// PEM bundle with the CA certificates to validate the chain against instead of the builtin roots
+        #[arg(long, value_name = "FILE")]
+        ca_file: Option<PathBuf>,
+
+        /// Plaintext protocol to upgrade the connection with before the TLS handshake
+        #[arg(long, value_name = "protocol", value_enum)]
+        starttls: Option<StartTls>,
+
+        /// Proxy URL (http://, socks5://) or `direct`, overrides HTTPS_PROXY
+        #[arg(long, value_name = "URL")]
+        proxy: Option<ProxySetting>,
+
+        /// Check every address the target host resolves to
+        #[arg(long, default_value = "false")]
+        all_addresses: bool,
+
+        /// Connect to ADDR instead of resolving the target, as curl does. HOST and PORT must match the target
+        #[arg(long, value_name = "HOST:PORT:ADDR", value_parser = parse_resolve)]
+        resolve: Option<(String, u32, IpAddr)>,
+
+        /// Server name to send and to match the certificate against instead of the target
+        #[arg(long, value_name = "NAME", conflicts_with = "no_sni")]
+        sni: Option<String>,
+
+        /// Send no server name, to get the default certificate of the server
+        #[arg(long, default_value = "false")]
+        no_sni: bool,
+
+        /// PEM file with the client certificate chain to present if the server requests one
+        #[arg(long, value_name = "FILE", requires = "client_key", conflicts_with = "client_pkcs12")]
+        client_cert: Option<PathBuf>,
+
+        /// PEM file with the private key of --client-cert
+        #[arg(long, value_name = "FILE", requires = "client_cert")]
+        client_key: Option<PathBuf>,
+
+        /// PKCS#12 file with the client certificate and key to present if the server requests one
+        #[arg(long, value_name = "FILE")]
+        client_pkcs12: Option<PathBuf>,
+
+        /// Password of --client-pkcs12
+        #[arg(long, value_name = "PASSWORD", requires = "client_pkcs12", group = "client_pkcs12_password")]
+        client_password: Option<String>,
+
+        /// Environment variable with the password of --client-pkcs12
+        #[arg(long, value_name = "NAME", requires = "client_pkcs12", group = "client_pkcs12_password")]
+        client_password_env: Option<String>,
+
+        /// File with the password of --client-pkcs12 on its first line
+        #[arg(long, value_name = "FILE", requires = "client_pkcs12", group = "client_pkcs12_password")]
+        client_password_file: Option<PathBuf>,
+
         /// Output the certificate instead of the table
         #[arg(short = 'o', long, value_name = "cert", default_value = "false")]
-        cert_output: bool
+        cert_output: bool,
+
+        /// Output the whole chain presented by the server as PEM bundle, implies --cert-output
+        #[arg(long, default_value = "false")]
+        full_chain: bool,
+
+        /// Format of the console output
+        #[arg(short = 'f', long, value_name = "format", value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+
+        /// Certificate fields of the table, csv and json output, e.g. `cn,issuer,not-after,sha256`
+        #[arg(long, value_name = "field", value_enum, value_delimiter = ',')]
+        fields: Vec<CertField>,
+
+        /// Lint rule not to apply to the certificates, may be repeated or comma separated
+        #[arg(long, value_name = "rule", value_parser = lint::rule_parser(), value_delimiter = ',')]
+        disable_lint: Vec<String>,
 
+        /// Base64 SHA-256 digest of a public key of which one certificate of the chain must match one, may be repeated
+        #[arg(long, value_name = "SHA256", value_parser = config::parse_pin)]
+        pin: Vec<String>,
 
+        /// JSON file remembering the certificates of the last run, to report renewals, replacements and new keys
+        #[arg(long, value_name = "FILE")]
+        state_file: Option<PathBuf>,
+    },
+
+    /// Inspects local certificate files (PEM, PEM bundle, DER, PKCS#12, JKS or JCEKS) and directories without any
+    /// network connection
+    Inspect {
+        /// Certificate files, keystores and directories
+        #[arg(value_name = "PATH", required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Password of PKCS#12, JKS and JCEKS keystores
+        #[arg(long, value_name = "PASSWORD", group = "keystore_password")]
+        password: Option<String>,
+
+        /// Environment variable with the password of the keystores
+        #[arg(long, value_name = "NAME", group = "keystore_password")]
+        password_env: Option<String>,
+
+        /// File with the password of the keystores on its first line
+        #[arg(long, value_name = "FILE", group = "keystore_password")]
+        password_file: Option<PathBuf>,
+
+        /// Remaining days below which the certificate is reported as warning
+        #[arg(short = 'w', long, value_name = "days", default_value = "15")]
+        warning_days: i64,
+
+        /// Remaining days below which the certificate is reported as critical
+        #[arg(short = 'c', long, value_name = "days", default_value = "7")]
+        critical_days: i64,
+
+        /// Output the certificate instead of the table
+        #[arg(short = 'o', long, value_name = "cert", default_value = "false")]
+        cert_output: bool,
+
+        /// Output the whole chain of each file as PEM bundle, implies --cert-output
+        #[arg(long, default_value = "false")]
+        full_chain: bool,
+
+        /// Format of the console output
+        #[arg(short = 'f', long, value_name = "format", value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+
+        /// Certificate fields of the table, csv and json output, e.g. `cn,issuer,not-after,sha256`
+        #[arg(long, value_name = "field", value_enum, value_delimiter = ',')]
+        fields: Vec<CertField>,
+
+        /// Lint rule not to apply to the certificates, may be repeated or comma separated
+        #[arg(long, value_name = "rule", value_parser = lint::rule_parser(), value_delimiter = ',')]
+        disable_lint: Vec<String>,
+
+        /// JSON file remembering the certificates of the last run, to report renewals, replacements and new keys
+        #[arg(long, value_name = "FILE")]
+        state_file: Option<PathBuf>,
     }
 }
 
@@ -63,93 +250,326 @@ fn main() -> ExitCode {
         Commands::InstallService { .. } => {
             ExitCode::SUCCESS
         }
-        Commands::Monitor { config_file, interval_hours: _interval_hours, daemon,cert_output } => {
-            if let Some(site_config) = load_config_file(config_file) {
-                monitor_cert_list(site_config, daemon, !daemon, true, cert_output);
+        Commands::Monitor { config_file, interval_hours, daemon, cert_output, full_chain, output_format, fields, workers, state_file } => {
+            let output_format = console_format(cert_output, full_chain, output_format);
+            if interval_hours.is_some() && output_format == OutputFormat::Json {
+                eprintln!("The json output needs a single run, use ndjson together with --interval-hours");
+                return ExitCode::FAILURE;
+            }
+            let Some(mut site_config) = load_config_file(&config_file) else {
+                eprintln!("Unable to load the config file {}", config_file.display());
+                return State::Unknown.into();
+            };
+            if let Some(workers) = workers {
+                site_config.workers = workers as usize;
+            }
+            if state_file.is_some() {
+                site_config.state_file = state_file;
+            }
+            if let Some((site, id)) = site_config.site_iter().find_map(|site| lint::unknown_rule(&site.disabled_lints).map(|id| (site, id))) {
+                eprintln!("Unknown lint rule {} disabled for {}", id, site.target_fqn);
+                return State::Unknown.into();
+            }
+            if let Err(e) = site_config.check_pins().and_then(|_| site_config.check_policy_references()) {
+                eprintln!("{}", e);
+                return State::Unknown.into();
+            }
+            let retrievers = match Retrievers::new(&site_config) {
+                Ok(retrievers) => retrievers,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return State::Unknown.into();
+                }
+            };
+            if daemon {
+                if let Err(e) = logger::init(&site_config.log_sink) {
+                    eprintln!("Unable to open the log sink {:?}: {}", site_config.log_sink, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+            let output = (!daemon).then(|| create_writer(output_format, &fields));
+            match interval_hours {
+                Some(interval_hours) => {
+                    monitor_cert_list_scheduled(&site_config, &retrievers, interval_hours, daemon, output);
+                    ExitCode::SUCCESS
+                }
+                None => {
+                    monitor_cert_list(&site_config, &retrievers, daemon, output).into()
+                }
             }
-            ExitCode::SUCCESS
         }
-        Commands::Check { target_host, target_port, cert_output } => {
-            let config = SiteConfig::simple(&target_host, target_port, 10);
-
-            monitor_cert_list(config, false, true, true, cert_output);
+        Commands::Check { target_host, target_port, warning_days, critical_days, ca_file, starttls, proxy, all_addresses, resolve, sni, no_sni, client_cert, client_key, client_pkcs12, client_password, client_password_env, client_password_file, cert_output, full_chain, output_format, fields, disable_lint, pin, state_file } => {
+            let connect_address = match resolve {
+                Some((host, port, address)) if host.eq_ignore_ascii_case(&target_host) && port == target_port => Some(address),
+                Some((host, port, _)) => {
+                    eprintln!("--resolve {}:{} does not match the target {}:{}", host, port, target_host, target_port);
+                    return ExitCode::FAILURE;
+                }
+                None => None,
+            };
+            let mut config = SiteConfig::simple(&target_host, target_port, warning_days, critical_days);
+            config.site_iter_mut().for_each(|site| {
+                site.connect_address = connect_address;
+                site.sni = if no_sni { Some(String::new()) } else { sni.clone() };
+                site.starttls = starttls;
+                site.connection.proxy = proxy.clone();
+                site.all_addresses = all_addresses;
+                site.disabled_lints = disable_lint.clone();
+                site.pins = pin.clone();
+            });
+            config.state_file = state_file;
+            if let Some(path) = ca_file {
+                config.add_trust_store("ca-file", TrustStoreConfig::File { path });
+            }
+            if let (Some(cert_path), Some(key_path)) = (client_cert, client_key) {
+                config.add_client_certificate("client-cert", ClientCertificateConfig::Pem { cert_path, key_path });
+            } else if let Some(path) = client_pkcs12 {
+                let password = client_password.map(Password::Value)
+                    .or(client_password_env.map(Password::Env))
+                    .or(client_password_file.map(Password::File));
+                config.add_client_certificate("client-cert", ClientCertificateConfig::Pkcs12 { path, password });
+            }
+            let output_format = console_format(cert_output, full_chain, output_format);
 
-            ExitCode::SUCCESS
+            let retrievers = match Retrievers::new(&config) {
+                Ok(retrievers) => retrievers,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return State::Unknown.into();
+                }
+            };
+            monitor_cert_list(&config, &retrievers, false, Some(create_writer(output_format, &fields))).into()
         }
+        Commands::Inspect { paths, password, password_env, password_file, warning_days, critical_days, cert_output, full_chain, output_format, fields, disable_lint, state_file } => {
+            let mut config = SiteConfig::files(&paths, warning_days, critical_days);
+            let keystore_password = password.map(Password::Value)
+                .or(password_env.map(Password::Env))
+                .or(password_file.map(Password::File));
+            config.site_iter_mut().for_each(|site| {
+                site.keystore_password = keystore_password.clone();
+                site.disabled_lints = disable_lint.clone();
+            });
+            config.state_file = state_file;
+            let output_format = console_format(cert_output, full_chain, output_format);
+            let retrievers = match Retrievers::new(&config) {
+                Ok(retrievers) => retrievers,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return State::Unknown.into();
+                }
+            };
+            monitor_cert_list(&config, &retrievers, false, Some(create_writer(output_format, &fields))).into()
+        }
+    }
+}
+
+/// Parses the `HOST:PORT:ADDR` of `--resolve`, ADDR may be an IPv6 address with or without brackets
+fn parse_resolve(value: &str) -> Result<(String, u32, IpAddr), String> {
+    let mut parts = value.splitn(3, ':');
+    let (Some(host), Some(port), Some(address)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err("expected HOST:PORT:ADDR".to_owned());
+    };
+    let port = port.parse::<u32>().map_err(|_| format!("{} is not a valid port", port))?;
+    let address = address.strip_prefix('[').and_then(|a| a.strip_suffix(']')).unwrap_or(address);
+    let address = address.parse::<IpAddr>().map_err(|_| format!("{} is not an IP address", address))?;
+    Ok((host.to_owned(), port, address))
+}
+
+/// The output format selected by `--cert-output`/`--full-chain`, or the one given by `--output-format`
+fn console_format(cert_output: bool, full_chain: bool, output_format: OutputFormat) -> OutputFormat {
+    if full_chain {
+        OutputFormat::PemChain
+    } else if cert_output {
+        OutputFormat::Pem
+    } else {
+        output_format
     }
 }
 
-fn monitor_cert_list(site_config: SiteConfig, _do_log_out : bool, do_console_out: bool, print_table_header : bool, cert_output: bool) {
+/// Checks all sites once and returns the worst state of them
+fn monitor_cert_list(site_config: &SiteConfig, retrievers: &Retrievers, do_log_out : bool, mut output: Option<Box<dyn ResultWriter>>) -> State {
+    let Ok(mut history) = load_history(site_config) else {
+        return State::Unknown;
+    };
+    if let Some(writer) = output.as_mut() {
+        report_write_error(writer.begin());
+    }
+
+    let log_target = do_log_out.then_some(site_config.log_target.as_str());
+    let sites: Vec<&Site> = site_config.site_iter().collect();
+    let state = check_sites(&sites, site_config, retrievers, log_target, &mut output, &mut history);
 
+    if let Some(writer) = output.as_mut() {
+        report_write_error(writer.finish());
+    }
+    state
+}
 
-    if do_console_out && print_table_header &&  !cert_output {
-        println!(" ! | {: <35} | {: <5} | {: <7} | {: <40} | {: <50}",
-            style("Target").white().bold(),
-            style("Port").white().bold(),
-            style("RemDays").white().bold(),
-            style("Serial").white().bold(),
-            style("CN").white().bold());
-        println!("---+-{:-<35}-+-{:-<5}-+-{:-<7}-+-{:-<40}-+-{:-<50}","","","","","");
+fn monitor_cert_list_scheduled(site_config: &SiteConfig, retrievers: &Retrievers, interval_hours: u32, do_log_out : bool, mut output: Option<Box<dyn ResultWriter>>) {
+    let (shutdown_tx, shutdown_rx) = mpsc::channel();
+    if let Err(e) = ctrlc::set_handler(move || { let _ = shutdown_tx.send(()); }) {
+        eprintln!("Unable to install the shutdown handler: {}", e);
+        return;
     }
+    let Ok(mut history) = load_history(site_config) else {
+        return;
+    };
 
-    site_config.site_iter().for_each(|site| {
-        let y = Style::new().yellow().bold();
-        let retriever = CertRetriever::new();
-        match retriever.get_target_cert_from_endpoint(&site.target_fqn, site.port) {
-            Ok(cert) => {
-                let expires = cert.get_remaining_days() < site.min_valid_days;
-
-                if do_console_out && !cert_output {
-                    if expires {
-                        println!(" {:<1} | {: <35} | {: >5} | {: >7} | {: <40} | {: <50}",
-                                 y.apply_to("!"),
-                                 y.apply_to(site.target_fqn.clone()),
-                                 y.apply_to(site.port),
-                                 y.apply_to(cert.get_remaining_days()),
-                                 y.apply_to(cert.get_serial_number()),
-                                 y.apply_to(cert.get_common_name()));
-                    } else {
-                        println!(" {:<1} | {: <35} | {: >5} | {: >7} | {: <40} | {: <50}", "",
-                                 site.target_fqn,
-                                 site.port as i64,
-                                 cert.get_remaining_days(),
-                                 cert.get_serial_number(),
-                                 cert.get_common_name());
-                    }
-                } else if cert_output {
-                    println!("{}", cert.get_pem());
-                }
-            }
-            Err(e) => {
-                if do_console_out {
-                    match e {
-                        CertError::InvalidFormat(e) => {
-                            print_err("InvalidCertFormat", &e, site);
-                        }
-                        CertError::TargetNotReachable(e) => {
-                            print_err("TargetNotReachable", &e, site);
-                        }
-                        CertError::TargetHasNoCertMatch(e) => {
-                            print_err("TargetHasNoCertMatch", &e, site);
-                        }
-                    }
-                }
+    if let Some(writer) = output.as_mut() {
+        report_write_error(writer.begin());
+    }
+
+    let log_target = do_log_out.then_some(site_config.log_target.as_str());
+    if let Some(target) = log_target {
+        log::info!(target: target, "Monitoring started with an interval of {} hours", interval_hours);
+    }
+
+    Scheduler::new(site_config, interval_hours).run(&shutdown_rx, |sites| {
+        check_sites(sites, site_config, retrievers, log_target, &mut output, &mut history);
+    });
+
+    if let Some(target) = log_target {
+        log::info!(target: target, "Monitoring stopped");
+    }
+    if let Some(writer) = output.as_mut() {
+        report_write_error(writer.finish());
+    }
+}
+
+/// The certificates seen by the last run if the config has a state file, prints the error if it can't be read
+fn load_history(site_config: &SiteConfig) -> Result<Option<CertificateHistory>, ()> {
+    site_config.state_file.as_deref()
+        .map(CertificateHistory::load)
+        .transpose()
+        .map_err(|e| eprintln!("{}", e))
+}
+
+/// Checks the sites in parallel and reports the results in the given order to `output` and/or as log
+/// records to `log_target`, together with the changes since the last run if there is a `history`.
+/// Returns the worst state of them.
+fn check_sites(sites: &[&Site], site_config: &SiteConfig, retrievers: &Retrievers, log_target: Option<&str>,
+               output: &mut Option<Box<dyn ResultWriter>>, history: &mut Option<CertificateHistory>) -> State {
+    let mut state = State::Ok;
+    pool::check_in_order(sites, site_config.parallelism(), |site| check_site(site, &site_config.site_policies(site), retrievers), |_, results| {
+        for mut result in results {
+            if let Some(history) = history.as_mut() {
+                history.record(&mut result);
             }
+            state = state.max(report_result(&result, log_target, output));
+        }
+    });
+    if let Some(Err(e)) = history.as_ref().map(CertificateHistory::save) {
+        if let Some(target) = log_target {
+            log::error!(target: target, "{}", e);
         }
-    })
+        eprintln!("{}", e);
+    }
+    state
 }
 
+/// Checks a single site against its expiry thresholds and `policies`, runs on one of the worker threads.
+/// Returns one result per address if the site is checked on all its addresses, one per certificate file
+/// if it is a `file` site.
+fn check_site(site: &Site, policies: &[(&str, &Policy)], retrievers: &Retrievers) -> Vec<CheckResult> {
+    let mut results = if site.site_type == SiteType::File {
+        inspect_site(site)
+    } else {
+        check_endpoint(site, retrievers)
+    };
+    results.iter_mut().for_each(|result| result.apply_policies(policies));
+    results
+}
 
+/// Retrieves the certificate of a `tls` site
+fn check_endpoint(site: &Site, retrievers: &Retrievers) -> Vec<CheckResult> {
+    let retriever = retrievers.for_site(site);
+    // a bug triggered by a single site must not stop the checks of all others
+    let outcomes = panic::catch_unwind(AssertUnwindSafe(|| {
+        if site.all_addresses {
+            retriever.get_site_cert_per_address(site)
+        } else {
+            let (endpoint_cert, attempts) = retriever.get_site_cert(site);
+            vec![(site.connect_address, endpoint_cert, attempts)]
+        }
+    })).unwrap_or_else(|_| vec![(None, Err(CertError::Internal(format!("The check of {}:{} panicked", site.target_fqn, site.port))), 1)]);
+    let mut results: Vec<CheckResult> = outcomes.into_iter().map(|(address, endpoint_cert, attempts)| {
+        let mut result = CheckResult::new(site, endpoint_cert);
+        result.address = address;
+        result.attempts = attempts;
+        result
+    }).collect();
+    CheckResult::check_node_consistency(&mut results);
+    results
+}
 
+/// Inspects the certificate files of a `file` site
+fn inspect_site(site: &Site) -> Vec<CheckResult> {
+    let outcomes = panic::catch_unwind(AssertUnwindSafe(|| inspect::inspect_site(site)))
+        .unwrap_or_else(|_| vec![FileOutcome {
+            file: PathBuf::from(&site.target_fqn),
+            alias: None,
+            result: Err(CertError::Internal(format!("The inspection of {} panicked", site.target_fqn))),
+        }]);
+    outcomes.into_iter().map(|outcome| {
+        let mut result = CheckResult::new(site, outcome.result);
+        result.port = 0;
+        result.file = Some(outcome.file);
+        result.alias = outcome.alias;
+        result
+    }).collect()
+}
 
-fn print_err(kind: &str, e: &str, site : &Site) {
-    let r = Style::new().red().bold();
-    println!(" {:<1} | {: <35} | {: >5} | {: >7} | {: <40} | {: <50}",
-             r.apply_to("!"),
-             r.apply_to(site.target_fqn.clone()),
-             r.apply_to(site.port),
-             r.apply_to("???"),
-             r.apply_to("<No Serial>"),
-             r.apply_to(format!("<{} : {}>", kind , e)));
+fn report_result(result: &CheckResult, log_target: Option<&str>, output: &mut Option<Box<dyn ResultWriter>>) -> State {
+    if let Some(target) = log_target {
+        log_result(target, result);
+    }
+    if let Some(writer) = output {
+        report_write_error(writer.write_result(result));
+    }
+    result.state
 }
 
+fn log_result(target: &str, result: &CheckResult) {
+    if let Some(cert) = &result.certificate {
+        let level = match result.state {
+            State::Ok => log::Level::Info,
+            State::Warning => log::Level::Warn,
+            State::Critical | State::Unknown => log::Level::Error,
+        };
+        let lints = result.lint_findings().map(|(_, finding)| finding.rule).collect::<Vec<_>>().join(" ");
+        let policy_violations = result.policy_violations.iter().map(|violation| violation.policy.as_str()).collect::<Vec<_>>().join(" ");
+        let changes = result.changes.iter().map(|change| change.event.label()).collect::<Vec<_>>().join(", ");
+        log::log!(target: target, level,
+            host = result.target.as_str(),
+            port = result.port,
+            state = result.state.label(),
+            severity = result.severity.as_deref().unwrap_or("none"),
+            validation = result.validation.as_ref().map_or("", |v| v.status.label()),
+            remaining_days = cert.get_remaining_days(),
+            serial = cert.get_serial_number(),
+            cn = cert.get_common_name(),
+            attempts = result.attempts,
+            inconsistent_nodes = result.inconsistent_nodes,
+            lints = lints.as_str(),
+            policy_violations = policy_violations.as_str(),
+            changes = changes.as_str(),
+            spki_sha256 = cert.get_spki_sha256(),
+            pin_mismatch = result.pinned == Some(false),
+            client_auth_requested = result.client_auth.as_ref().is_some_and(|client_auth| client_auth.requested);
+            "Certificate of {} expires in {} days", result.endpoint(), cert.get_remaining_days());
+    } else if let Some(e) = &result.error {
+        log::error!(target: target,
+            host = result.target.as_str(),
+            port = result.port,
+            state = result.state.label(),
+            error_kind = e.kind(),
+            attempts = result.attempts;
+            "Check of {} failed after {} attempts: {}", result.endpoint(), result.attempts, e.message());
+    }
+}
+
+fn report_write_error(result: std::io::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("Unable to write the output: {}", e);
+    }
+}
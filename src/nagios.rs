@@ -0,0 +1,227 @@
+use crate::cert_retriever::CertError;
+use crate::output::{CheckResult, ResultWriter, PIN_MISMATCH};
+use crate::severity::threshold;
+use crate::validation::ChainOrder;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::Write;
+use std::process::ExitCode;
+
+/// Service states of the Nagios plugin API, ordered by increasing badness
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum State {
+    #[serde(alias = "ok")]
+    Ok,
+    #[serde(alias = "warning")]
+    Warning,
+    #[serde(alias = "unknown")]
+    Unknown,
+    #[serde(alias = "critical")]
+    Critical,
+}
+
+impl State {
+    pub fn label(&self) -> &'static str {
+        match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// Exit code as expected by Nagios and Icinga
+    pub fn code(&self) -> u8 {
+        match self {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Critical => 2,
+            State::Unknown => 3,
+        }
+    }
+}
+
+impl From<State> for ExitCode {
+    fn from(state: State) -> ExitCode {
+        ExitCode::from(state.code())
+    }
+}
+
+/// State reported for a site whose certificate could not be retrieved
+pub fn error_state(e: &CertError) -> State {
+    match e {
+        // the service is down or serves the wrong certificate
+        CertError::TargetNotReachable(_) |
+        CertError::DnsResolutionFailed(_) |
+        CertError::ConnectionRefused(_) |
+        CertError::ConnectTimeout(_) |
+        CertError::HandshakeAlert { .. } |
+        CertError::ProtocolMismatch(_) |
+        CertError::HandshakeTimeout(_) |
+        CertError::StartTlsNotOffered(_) |
+        CertError::StartTlsFailed(_) |
+        CertError::HandshakeFailed(_) |
+        CertError::EmptyPeerChain(_) |
+        CertError::TargetHasNoCertMatch(_) => State::Critical,
+        // we got something but can't tell anything about its validity
+        CertError::InvalidFormat(_) => State::Unknown,
+        // the config is broken or the check itself failed, not the service
+        CertError::InvalidServerName(_) |
+        CertError::ProxyFailed(_) |
+        CertError::FileUnreadable(_) |
+        CertError::KeystoreFailed(_) |
+        CertError::Internal(_) => State::Unknown,
+    }
+}
+
+/// Collects all results and writes a single status line with performance data, as
+/// expected from a Nagios/Icinga plugin
+pub struct NagiosWriter<W: Write> {
+    out: W,
+    results: Vec<CheckResult>,
+}
+
+impl<W: Write> NagiosWriter<W> {
+    pub fn new(out: W) -> NagiosWriter<W> {
+        NagiosWriter { out, results: Vec::new() }
+    }
+
+    fn detail(result: &CheckResult) -> String {
+        match (&result.remaining_days, &result.error) {
+            (Some(days), _) => {
+                let mut detail = format!("{} expires in {} days", result.endpoint(), days);
+                for element in result.expiring_chain_elements() {
+                    detail.push_str(&format!(", chain certificate '{}' expires in {} days",
+                        element.certificate.get_common_name(), element.remaining_days));
+                }
+                if let Some(order) = result.chain_order.filter(|order| *order != ChainOrder::Ordered) {
+                    detail.push_str(&format!(", chain {}", order.label().to_lowercase()));
+                }
+                if let Some(validation) = result.validation.as_ref().filter(|v| !v.is_valid()) {
+                    detail.push_str(&format!(", validation failed: {}", validation.status.label()));
+                }
+                for (certificate, finding) in result.lint_findings() {
+                    detail.push_str(&format!(", lint {} of '{}': {}", finding.rule, certificate.get_common_name(), finding.message));
+                }
+                for violation in &result.policy_violations {
+                    detail.push_str(&format!(", policy {}: {}", violation.policy, violation.message));
+                }
+                for change in &result.changes {
+                    detail.push_str(&format!(", certificate {}: {}", change.event.label(), change.message));
+                }
+                if result.pinned == Some(false) {
+                    detail.push_str(&format!(", pin mismatch: {}", PIN_MISMATCH));
+                }
+                if result.inconsistent_nodes {
+                    detail.push_str(", other addresses present a different certificate");
+                }
+                detail
+            }
+            (None, Some(e)) if result.attempts > 1 => format!("{} {} after {} attempts: {}",
+                result.endpoint(), e.kind(), result.attempts, e.message()),
+            (None, Some(e)) => format!("{} {}: {}", result.endpoint(), e.kind(), e.message()),
+            (None, None) => format!("{} no result", result.endpoint()),
+        }
+    }
+
+    fn perf_data(label: &str, result: &CheckResult) -> String {
+        let value = result.remaining_days.map_or("U".to_owned(), |days| days.to_string());
+        let warning = threshold(&result.severity_levels, State::Warning).map(|days| days.to_string()).unwrap_or_default();
+        let critical = threshold(&result.severity_levels, State::Critical).map(|days| days.to_string()).unwrap_or_default();
+        format!("{}={};{};{}", label, value, warning, critical)
+    }
+
+    fn status_line(&self) -> String {
+        let state = self.results.iter().map(|r| r.state).max().unwrap_or(State::Unknown);
+        match self.results.as_slice() {
+            [] => format!("CERT {} - no sites checked", state.label()),
+            [result] => format!("CERT {} - {} | {}", state.label(), Self::detail(result), Self::perf_data("days_left", result)),
+            results => {
+                let count = |s: State| results.iter().filter(|r| r.state == s).count();
+                let problems: Vec<String> = results.iter()
+                    .filter(|r| r.state != State::Ok)
+                    .map(Self::detail)
+                    .collect();
+                let perf_data: Vec<String> = results.iter()
+                    .map(|r| Self::perf_data(&format!("'{}'", r.endpoint()), r))
+                    .collect();
+                let mut line = format!("CERT {} - {} critical, {} warning, {} unknown, {} ok",
+                    state.label(), count(State::Critical), count(State::Warning), count(State::Unknown), count(State::Ok));
+                if !problems.is_empty() {
+                    line.push_str(": ");
+                    line.push_str(&problems.join(", "));
+                }
+                format!("{} | {}", line, perf_data.join(" "))
+            }
+        }
+    }
+}
+
+impl<W: Write> ResultWriter for NagiosWriter<W> {
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        self.results.push(result.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        writeln!(self.out, "{}", self.status_line())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Site;
+    use crate::cert_retriever::{EndpointCertificate, SimpleCertificate};
+    use crate::client_auth::ClientAuth;
+    use crate::validation::{Validation, ValidationStatus};
+    use rustls::pki_types::CertificateDer;
+
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+
+    fn site(json: &str) -> Site {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_error_state() {
+        assert_eq!(error_state(&CertError::TargetNotReachable("".to_owned())), State::Critical);
+        assert_eq!(error_state(&CertError::TargetHasNoCertMatch("".to_owned())), State::Critical);
+        assert_eq!(error_state(&CertError::InvalidFormat("".to_owned())), State::Unknown);
+        assert_eq!(error_state(&CertError::HandshakeAlert { code: 40, message: "".to_owned() }), State::Critical);
+        assert_eq!(error_state(&CertError::InvalidServerName("".to_owned())), State::Unknown);
+        assert!(State::Critical > State::Unknown && State::Unknown > State::Warning && State::Warning > State::Ok);
+    }
+
+    #[test]
+    fn test_single_status_line() {
+        let site = site(r#"{ "targetFqn": "schludri.e3ag.ch", "service": "flup", "warningDays": 30, "criticalDays": 15 }"#);
+        let mut writer = NagiosWriter::new(Vec::new());
+        writer.write_result(&CheckResult::new(&site, Err(CertError::TargetNotReachable("Target schludri.e3ag.ch:443 is unreachable".to_owned())))).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(String::from_utf8(writer.out).unwrap(),
+            "CERT CRITICAL - schludri.e3ag.ch:443 TargetNotReachable: Target schludri.e3ag.ch:443 is unreachable | days_left=U;30;15\n");
+    }
+
+    #[test]
+    fn test_multi_status_line() {
+        let gitea = site(r#"{ "targetFqn": "gitea.tschirky.ch", "service": "other", "warningDays": -100000, "criticalDays": -200000 }"#);
+        let broken = site(r#"{ "targetFqn": "www.tschirky.ch", "service": "other" }"#);
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from(GITEA_CERT)).map(|certificate| EndpointCertificate {
+            chain: vec![certificate.clone()],
+            certificate,
+            chain_order: ChainOrder::Ordered,
+            validation: Some(Validation { status: ValidationStatus::Valid, detail: None }),
+            client_auth: ClientAuth::default(),
+        });
+        let mut writer = NagiosWriter::new(Vec::new());
+        writer.write_result(&CheckResult::new(&gitea, cert)).unwrap();
+        writer.write_result(&CheckResult::new(&broken, Err(CertError::InvalidFormat("bad".to_owned())))).unwrap();
+
+        let line = writer.status_line();
+        assert!(line.starts_with("CERT UNKNOWN - 0 critical, 0 warning, 1 unknown, 1 ok: www.tschirky.ch:443 InvalidFormat: bad | 'gitea.tschirky.ch:443'="), "{}", line);
+        assert!(line.ends_with(";-100000;-200000 'www.tschirky.ch:443'=U;15;7"), "{}", line);
+    }
+}
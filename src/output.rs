@@ -0,0 +1,953 @@
+use crate::cert_retriever::{CertError, EndpointCertificate, SimpleCertificate};
+use crate::client_auth::ClientAuth;
+use crate::config::Site;
+use crate::history::CertificateChange;
+use crate::lint::LintFinding;
+use crate::nagios::{error_state, NagiosWriter, State};
+use crate::policy::{Policy, PolicyViolation};
+use crate::severity::{reached_level, SeverityLevel};
+use crate::text::TextWriter;
+use crate::validation::{ChainOrder, Validation};
+use clap::ValueEnum;
+use console::Style;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// Coloured table for the console
+    Table,
+    /// PEM encoded certificates
+    Pem,
+    /// One JSON document containing all results
+    Json,
+    /// One JSON object per line, written as soon as a site is checked
+    Ndjson,
+    /// Comma separated values with a header line
+    Csv,
+    /// Single status line with performance data for Nagios/Icinga
+    Nagios,
+    /// PEM bundle of the whole chain as presented by the server
+    PemChain,
+    /// Decoded view of every certificate of the chain like `openssl x509 -text`
+    Text,
+}
+
+/// Certificate field which can be selected as column of the table and csv output or as property of the json
+/// output
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum CertField {
+    /// Common name of the subject
+    Cn,
+    /// Distinguished name of the subject
+    Subject,
+    /// Distinguished name of the issuer
+    Issuer,
+    Serial,
+    NotBefore,
+    NotAfter,
+    /// DNS names of the subject alternative names
+    SanDns,
+    /// IP addresses of the subject alternative names
+    SanIp,
+    /// Whether the certificate is a CA certificate
+    IsCa,
+    SignatureAlgorithm,
+    /// Algorithm and size or curve of the public key, e.g. `RSA 2048` or `EC P-256`
+    PublicKey,
+    KeyUsage,
+    ExtendedKeyUsage,
+    /// SHA-1 fingerprint of the certificate
+    Sha1,
+    /// SHA-256 fingerprint of the certificate
+    Sha256,
+    /// Base64 SHA-256 digest of the subject public key info, the value of a pin
+    SpkiSha256,
+    /// Subject key identifier
+    Ski,
+    /// Authority key identifier
+    Aki,
+    /// OCSP responder URLs of the authority information access
+    Ocsp,
+    /// CA issuers URLs of the authority information access
+    CaIssuers,
+    /// CRL distribution point URLs
+    Crl,
+    /// Certificate policy OIDs
+    Policies,
+}
+
+/// Columns of the table if no fields are selected
+const TABLE_FIELDS: &[CertField] = &[CertField::Serial, CertField::Cn];
+/// Certificate columns of the csv output if no fields are selected
+const CSV_FIELDS: &[CertField] = &[CertField::NotAfter, CertField::Serial, CertField::Cn, CertField::SanDns,
+                                   CertField::SanIp, CertField::IsCa];
+/// Describes a result of which no certificate matches the pins of the site
+pub const PIN_MISMATCH: &str = "No key of the chain matches the pins";
+
+impl CertField {
+    /// Table header, table column width, csv column and json property of the field
+    fn names(self) -> (&'static str, usize, &'static str, &'static str) {
+        match self {
+            CertField::Cn => ("CN", 50, "common_name", "commonName"),
+            CertField::Subject => ("Subject", 60, "subject", "subject"),
+            CertField::Issuer => ("Issuer", 60, "issuer", "issuer"),
+            CertField::Serial => ("Serial", 40, "serial_number", "serialNumber"),
+            CertField::NotBefore => ("Not Before", 20, "not_before", "notBefore"),
+            CertField::NotAfter => ("Not After", 20, "expiration_date", "expirationDate"),
+            CertField::SanDns => ("SAN DNS", 50, "san_dns_names", "sanDnsNames"),
+            CertField::SanIp => ("SAN IP", 30, "san_ip_addresses", "sanIpAddresses"),
+            CertField::IsCa => ("CA", 5, "is_ca", "isCa"),
+            CertField::SignatureAlgorithm => ("Signature", 23, "signature_algorithm", "signatureAlgorithm"),
+            CertField::PublicKey => ("Public Key", 10, "public_key", "publicKey"),
+            CertField::KeyUsage => ("Key Usage", 35, "key_usage", "keyUsage"),
+            CertField::ExtendedKeyUsage => ("Ext Key Usage", 25, "extended_key_usage", "extendedKeyUsage"),
+            CertField::Sha1 => ("SHA-1", 40, "sha1_fingerprint", "sha1Fingerprint"),
+            CertField::Sha256 => ("SHA-256", 64, "sha256_fingerprint", "sha256Fingerprint"),
+            CertField::SpkiSha256 => ("SPKI SHA-256", 44, "spki_sha256", "spkiSha256"),
+            CertField::Ski => ("SKI", 40, "subject_key_id", "subjectKeyId"),
+            CertField::Aki => ("AKI", 40, "authority_key_id", "authorityKeyId"),
+            CertField::Ocsp => ("OCSP", 40, "ocsp_urls", "ocspUrls"),
+            CertField::CaIssuers => ("CA Issuers", 40, "ca_issuers_urls", "caIssuersUrls"),
+            CertField::Crl => ("CRL", 40, "crl_distribution_points", "crlDistributionPoints"),
+            CertField::Policies => ("Policies", 30, "policy_oids", "policyOids"),
+        }
+    }
+
+    fn header(self) -> &'static str {
+        self.names().0
+    }
+
+    fn width(self) -> usize {
+        self.names().1
+    }
+
+    fn column(self) -> &'static str {
+        self.names().2
+    }
+
+    fn json_property(self) -> &'static str {
+        self.names().3
+    }
+
+    /// The field as shown in the table and csv output, lists separated by spaces
+    fn value(self, cert: &SimpleCertificate) -> String {
+        match self {
+            CertField::Cn => cert.get_common_name().to_owned(),
+            CertField::Subject => cert.get_subject().to_owned(),
+            CertField::Issuer => cert.get_issuer().to_owned(),
+            CertField::Serial => cert.get_serial_number().to_owned(),
+            CertField::NotBefore => format_rfc3339(cert.get_not_before()),
+            CertField::NotAfter => format_rfc3339(cert.get_expiration_date()),
+            CertField::SanDns => cert.get_san_dns_names().join(" "),
+            CertField::SanIp => cert.get_san_ip_addresses().iter().map(|ip| ip.to_string()).collect::<Vec<_>>().join(" "),
+            CertField::IsCa => cert.is_ca().to_string(),
+            CertField::SignatureAlgorithm => cert.get_signature_algorithm().to_owned(),
+            CertField::PublicKey => cert.get_public_key().to_string(),
+            CertField::KeyUsage => cert.get_key_usage().join(" "),
+            CertField::ExtendedKeyUsage => cert.get_extended_key_usage().join(" "),
+            CertField::Sha1 => cert.get_sha1_fingerprint().to_owned(),
+            CertField::Sha256 => cert.get_sha256_fingerprint().to_owned(),
+            CertField::SpkiSha256 => cert.get_spki_sha256().to_owned(),
+            CertField::Ski => cert.get_subject_key_id().unwrap_or_default().to_owned(),
+            CertField::Aki => cert.get_authority_key_id().unwrap_or_default().to_owned(),
+            CertField::Ocsp => cert.get_ocsp_urls().join(" "),
+            CertField::CaIssuers => cert.get_ca_issuers_urls().join(" "),
+            CertField::Crl => cert.get_crl_distribution_points().join(" "),
+            CertField::Policies => cert.get_policy_oids().join(" "),
+        }
+    }
+}
+
+/// `fields` or `defaults` if no fields are selected
+fn selected_fields(fields: &[CertField], defaults: &[CertField]) -> Vec<CertField> {
+    if fields.is_empty() { defaults.to_vec() } else { fields.to_vec() }
+}
+
+/// Outcome of the check of a single site
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckResult {
+    pub target: String,
+    pub port: u32,
+    pub service: String,
+    /// The address checked if it is configured or the site is checked on all its addresses
+    pub address: Option<IpAddr>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub checked_at: OffsetDateTime,
+    pub severity_levels: Vec<SeverityLevel>,
+    pub remaining_days: Option<i64>,
+    /// Name of the most severe level reached by the remaining days
+    pub severity: Option<String>,
+    pub state: State,
+    pub certificate: Option<SimpleCertificate>,
+    /// All certificates presented by the server, each checked against the severity levels
+    pub chain: Vec<ChainElement>,
+    pub chain_order: Option<ChainOrder>,
+    /// Validation of the presented chain against the trust anchors
+    pub validation: Option<Validation>,
+    pub error: Option<CertError>,
+    /// Number of connection attempts, more than one if transient failures were retried
+    pub attempts: u32,
+    /// Whether other addresses of the site present a different certificate
+    pub inconsistent_nodes: bool,
+    /// Whether the server requested a client certificate, unknown if the handshake failed
+    pub client_auth: Option<ClientAuth>,
+    /// The certificate file the result was read from if the site is a `file` site
+    pub file: Option<PathBuf>,
+    /// Alias of the keystore entry the certificate was read from
+    pub alias: Option<String>,
+    /// Requirements of the policies of the site which the certificate does not meet
+    pub policy_violations: Vec<PolicyViolation>,
+    /// Changes of the certificate since the last run, empty without a state file
+    pub changes: Vec<CertificateChange>,
+    /// Whether a certificate of the chain matches one of the pins of the site, unknown if the site has no pins
+    pub pinned: Option<bool>,
+}
+
+/// Certificate of the presented chain with the severity level reached by its own expiry
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainElement {
+    pub certificate: SimpleCertificate,
+    pub remaining_days: i64,
+    pub severity: Option<String>,
+    pub state: State,
+    /// Violated lint rules except the ones disabled for the site
+    pub lints: Vec<LintFinding>,
+}
+
+impl ChainElement {
+    fn new(certificate: SimpleCertificate, severity_levels: &[SeverityLevel], disabled_lints: &[String]) -> ChainElement {
+        let remaining_days = certificate.get_remaining_days();
+        let level = reached_level(severity_levels, remaining_days);
+        let lints = certificate.get_lints().iter()
+            .filter(|finding| !disabled_lints.iter().any(|id| id == finding.rule))
+            .cloned()
+            .collect();
+        ChainElement {
+            certificate,
+            remaining_days,
+            severity: level.map(|level| level.name.clone()),
+            state: level.map_or(State::Ok, |level| level.state),
+            lints,
+        }
+    }
+}
+
+impl CheckResult {
+    pub fn new(site: &Site, result: Result<EndpointCertificate, CertError>) -> CheckResult {
+        let severity_levels = site.severity_levels();
+        let (certificate, chain, chain_order, validation, client_auth, error) = match result {
+            Ok(cert) => {
+                let chain: Vec<ChainElement> = cert.chain.into_iter()
+                    .map(|certificate| ChainElement::new(certificate, &severity_levels, &site.disabled_lints))
+                    .collect();
+                (Some(cert.certificate), chain, Some(cert.chain_order), cert.validation, Some(cert.client_auth), None)
+            }
+            Err(e) => (None, Vec::new(), None, None, None, Some(e)),
+        };
+        let remaining_days = certificate.as_ref().map(|cert| cert.get_remaining_days());
+        let level = remaining_days.and_then(|days| reached_level(&severity_levels, days));
+        let mut state = match (remaining_days, &error) {
+            (Some(_), _) => level.map_or(State::Ok, |level| level.state),
+            (None, Some(e)) => error_state(e),
+            (None, None) => State::Unknown,
+        };
+        // an expiring intermediate breaks the service as well as an expiring end entity certificate
+        state = chain.iter().map(|element| element.state).fold(state, State::max);
+        if chain_order.is_some_and(|order| order != ChainOrder::Ordered) {
+            state = state.max(State::Warning);
+        }
+        state = chain.iter()
+            .flat_map(|element| &element.lints)
+            .map(|finding| finding.severity.state())
+            .fold(state, State::max);
+        if site.validate_chain && validation.as_ref().is_some_and(|v| !v.is_valid()) {
+            state = State::Critical;
+        }
+        let pinned = (!site.pins.is_empty() && certificate.is_some()).then(|| chain.iter()
+            .any(|element| site.pin_digests().any(|pin| pin == element.certificate.get_spki_sha256())));
+        if pinned == Some(false) {
+            state = State::Critical;
+        }
+        let severity = level.map(|level| level.name.clone());
+        CheckResult {
+            target: site.target_fqn.clone(),
+            port: site.port,
+            service: site.service.clone(),
+            address: None,
+            checked_at: OffsetDateTime::now_utc(),
+            severity_levels,
+            remaining_days,
+            severity,
+            state,
+            certificate,
+            chain,
+            chain_order,
+            validation,
+            error,
+            attempts: 1,
+            inconsistent_nodes: false,
+            client_auth,
+            file: None,
+            alias: None,
+            policy_violations: Vec::new(),
+            changes: Vec::new(),
+            pinned,
+        }
+    }
+
+    /// Checks the certificate against the `policies` of the site, each violation raises the state to the one
+    /// of its policy
+    pub fn apply_policies(&mut self, policies: &[(&str, &Policy)]) {
+        if let Some(cert) = &self.certificate {
+            self.policy_violations = policies.iter()
+                .flat_map(|(name, policy)| policy.check(name, cert))
+                .collect();
+            self.state = self.policy_violations.iter().map(|violation| violation.state).fold(self.state, State::max);
+        }
+    }
+
+    /// `target:port`, followed by the address if the result is the one of a single address, or the
+    /// certificate file, followed by the alias if it is a keystore
+    pub fn endpoint(&self) -> String {
+        match (&self.file, self.address) {
+            (Some(file), _) => self.file_label(file),
+            (None, Some(address)) => format!("{}:{} ({})", self.target, self.port, address),
+            (None, None) => format!("{}:{}", self.target, self.port),
+        }
+    }
+
+    fn file_label(&self, file: &Path) -> String {
+        match &self.alias {
+            Some(alias) => format!("{} ({})", file.display(), alias),
+            None => file.display().to_string(),
+        }
+    }
+
+    /// The port column of the table, empty for certificate files
+    fn port_label(&self) -> String {
+        match self.file {
+            Some(_) => String::new(),
+            None => self.port.to_string(),
+        }
+    }
+
+    /// Marks the results of the addresses of a site as inconsistent and raises them to at least warning
+    /// if they don't all present the same certificate
+    pub fn check_node_consistency(results: &mut [CheckResult]) {
+        let serials: BTreeSet<&str> = results.iter()
+            .filter_map(|result| result.certificate.as_ref())
+            .map(|cert| cert.get_serial_number())
+            .collect();
+        if serials.len() > 1 {
+            for result in results.iter_mut().filter(|result| result.certificate.is_some()) {
+                result.inconsistent_nodes = true;
+                result.state = result.state.max(State::Warning);
+            }
+        }
+    }
+
+    /// Certificates of the chain other than the matched one which reached a severity level
+    pub fn expiring_chain_elements(&self) -> impl Iterator<Item=&ChainElement> {
+        let serial = self.certificate.as_ref().map(|cert| cert.get_serial_number());
+        self.chain.iter()
+            .filter(move |element| Some(element.certificate.get_serial_number()) != serial)
+            .filter(|element| element.severity.is_some())
+    }
+
+    /// Lint findings of all certificates of the chain, each with the certificate it was found in
+    pub fn lint_findings(&self) -> impl Iterator<Item=(&SimpleCertificate, &LintFinding)> {
+        self.chain.iter()
+            .flat_map(|element| element.lints.iter().map(move |finding| (&element.certificate, finding)))
+    }
+
+    fn validation_label(&self) -> &'static str {
+        self.validation.as_ref().map_or("", |v| v.status.label())
+    }
+}
+
+/// Receives the results of the checked sites and renders them in one of the [OutputFormat]s
+pub trait ResultWriter {
+    /// Called once before the first result
+    fn begin(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()>;
+
+    /// Called once after the last result
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates the writer of `format` showing the certificate `fields`, or the default fields of the format if
+/// none are selected
+pub fn create_writer(format: OutputFormat, fields: &[CertField]) -> Box<dyn ResultWriter> {
+    match format {
+        OutputFormat::Table => Box::new(TableWriter { out: io::stdout(), fields: selected_fields(fields, TABLE_FIELDS) }),
+        OutputFormat::Pem => Box::new(PemWriter { out: io::stdout(), full_chain: false }),
+        OutputFormat::PemChain => Box::new(PemWriter { out: io::stdout(), full_chain: true }),
+        OutputFormat::Json => Box::new(JsonWriter { out: io::stdout(), results: Vec::new(), fields: fields.to_vec() }),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter { out: io::stdout(), fields: fields.to_vec() }),
+        OutputFormat::Csv => Box::new(CsvWriter { out: csv::Writer::from_writer(io::stdout()), fields: selected_fields(fields, CSV_FIELDS) }),
+        OutputFormat::Nagios => Box::new(NagiosWriter::new(io::stdout())),
+        OutputFormat::Text => Box::new(TextWriter::new(io::stdout())),
+    }
+}
+
+struct TableWriter<W: Write> {
+    out: W,
+    /// Certificate fields shown after the validation column
+    fields: Vec<CertField>,
+}
+
+impl<W: Write> TableWriter<W> {
+    /// Writes the `!`, target, port, remaining days and validation `columns` followed by one cell per field,
+    /// all in `style` if it is given
+    fn write_row(&mut self, style: Option<&Style>, columns: [&str; 5], cells: &[String]) -> io::Result<()> {
+        let apply = |text: String| match style {
+            Some(style) => style.apply_to(text).to_string(),
+            None => text,
+        };
+        let [bang, target, port, days, validation] = columns;
+        let mut row = format!(" {} | {} | {} | {} | {}",
+                              apply(format!("{:<1}", bang)),
+                              apply(format!("{: <35}", target)),
+                              apply(format!("{: >5}", port)),
+                              apply(format!("{: >7}", days)),
+                              apply(format!("{: <19}", validation)));
+        for (field, cell) in self.fields.iter().zip(cells) {
+            row.push_str(&format!(" | {}", apply(format!("{: <width$}", cell, width = field.width()))));
+        }
+        writeln!(self.out, "{}", row)
+    }
+
+    /// Cells which are empty except the last one
+    fn last_cell(&self, text: String) -> Vec<String> {
+        let mut cells = vec![String::new(); self.fields.len()];
+        if let Some(last) = cells.last_mut() {
+            *last = text;
+        }
+        cells
+    }
+
+    fn cells(&self, cert: &SimpleCertificate) -> Vec<String> {
+        self.fields.iter().map(|field| field.value(cert)).collect()
+    }
+}
+
+impl<W: Write> ResultWriter for TableWriter<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        let headers: Vec<String> = self.fields.iter().map(|field| field.header().to_owned()).collect();
+        self.write_row(Some(&Style::new().white().bold()), ["!", "Target", "Port", "RemDays", "Validation"], &headers)?;
+        let mut separator = format!("---+-{:-<35}-+-{:-<5}-+-{:-<7}-+-{:-<19}", "", "", "", "");
+        for field in &self.fields {
+            separator.push_str(&format!("-+-{:-<width$}", "", width = field.width()));
+        }
+        writeln!(self.out, "{}", separator)
+    }
+
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        let target = match (&result.file, result.address) {
+            (Some(file), _) => result.file_label(file),
+            (None, Some(address)) => format!("{} ({})", result.target, address),
+            (None, None) => result.target.clone(),
+        };
+        match (&result.certificate, &result.error) {
+            (Some(cert), _) => {
+                let (style, bang) = if result.severity.is_some() || result.state != State::Ok {
+                    (Some(state_style(result.state)), "!")
+                } else {
+                    (None, "")
+                };
+                let days = cert.get_remaining_days().to_string();
+                self.write_row(style.as_ref(), [bang, &target, &result.port_label(), &days, result.validation_label()],
+                               &self.cells(cert))?;
+                for element in result.expiring_chain_elements() {
+                    self.write_row(Some(&state_style(element.state)),
+                                   ["!", "  \u{2514} chain", "", &element.remaining_days.to_string(), ""],
+                                   &self.cells(&element.certificate))?;
+                }
+                for (certificate, finding) in result.lint_findings() {
+                    self.write_row(Some(&state_style(finding.severity.state())),
+                                   ["!", &format!("  \u{2514} lint {}", finding.rule), "", "", finding.severity.label()],
+                                   &self.last_cell(format!("{}: {}", certificate.get_common_name(), finding.message)))?;
+                }
+                for violation in &result.policy_violations {
+                    self.write_row(Some(&state_style(violation.state)),
+                                   ["!", &format!("  \u{2514} policy {}", violation.policy), "", "", violation.requirement],
+                                   &self.last_cell(violation.message.clone()))?;
+                }
+                for change in &result.changes {
+                    let state = change.event.state();
+                    let (style, bang) = if state != State::Ok { (Some(state_style(state)), "!") } else { (None, "") };
+                    self.write_row(style.as_ref(), [bang, &format!("  \u{2514} {}", change.event.label()), "", "", ""],
+                                   &self.last_cell(change.message.clone()))?;
+                }
+                if result.pinned == Some(false) {
+                    self.write_row(Some(&state_style(State::Critical)), ["!", "  \u{2514} pin mismatch", "", "", ""],
+                                   &self.last_cell(PIN_MISMATCH.to_owned()))?;
+                }
+                if result.inconsistent_nodes {
+                    self.write_row(Some(&state_style(State::Warning)),
+                                   ["!", "  \u{2514} other addresses differ", "", "", ""],
+                                   &self.last_cell(String::new()))?;
+                }
+                if let Some(client_auth) = result.client_auth.as_ref().filter(|client_auth| client_auth.requested) {
+                    let label = if client_auth.presented { "  \u{2514} client cert presented" } else { "  \u{2514} client cert requested" };
+                    self.write_row(None, ["", label, "", "", ""], &self.last_cell(client_auth.ca_names.join("; ")))?;
+                }
+                Ok(())
+            }
+            (None, Some(e)) => {
+                let mut cells = self.last_cell(format!("<{} : {}>", e.kind(), e.message()));
+                if cells.len() > 1 {
+                    cells[0] = format!("<No {}>", self.fields[0].header());
+                }
+                self.write_row(Some(&Style::new().red().bold()), ["!", &target, &result.port_label(), "???", ""], &cells)
+            }
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Colour of a table row which reached a severity level
+pub fn state_style(state: State) -> Style {
+    match state {
+        State::Ok => Style::new().cyan().bold(),
+        State::Warning => Style::new().yellow().bold(),
+        State::Unknown => Style::new().magenta().bold(),
+        State::Critical => Style::new().red().bold(),
+    }
+}
+
+struct PemWriter<W: Write> {
+    out: W,
+    /// Whether to write all certificates presented by the server instead of the matched one only
+    full_chain: bool,
+}
+
+impl<W: Write> ResultWriter for PemWriter<W> {
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        if self.full_chain && !result.chain.is_empty() {
+            for element in &result.chain {
+                write!(self.out, "{}", element.certificate.get_pem())?;
+            }
+            writeln!(self.out)?;
+        } else if let Some(cert) = &result.certificate {
+            writeln!(self.out, "{}", cert.get_pem())?;
+        } else if let Some(e) = &result.error {
+            eprintln!("{} {} : {}", result.endpoint(), e.kind(), e.message());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    results: Vec<JsonResult<'a>>,
+}
+
+/// A [CheckResult] whose certificates only have the selected `fields`, or all if none are selected
+struct JsonResult<'a> {
+    result: &'a CheckResult,
+    fields: &'a [CertField],
+}
+
+impl Serialize for JsonResult<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.fields.is_empty() {
+            return self.result.serialize(serializer);
+        }
+        let mut value = serde_json::to_value(self.result).map_err(serde::ser::Error::custom)?;
+        let properties: Vec<&str> = self.fields.iter().map(|field| field.json_property()).collect();
+        let select = |certificate: &mut Value| {
+            if let Some(certificate) = certificate.as_object_mut() {
+                certificate.retain(|property, _| properties.contains(&property.as_str()));
+            }
+        };
+        select(&mut value["certificate"]);
+        if let Some(chain) = value["chain"].as_array_mut() {
+            chain.iter_mut().for_each(|element| select(&mut element["certificate"]));
+        }
+        value.serialize(serializer)
+    }
+}
+
+struct JsonWriter<W: Write> {
+    out: W,
+    results: Vec<CheckResult>,
+    fields: Vec<CertField>,
+}
+
+impl<W: Write> ResultWriter for JsonWriter<W> {
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        self.results.push(result.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let results = self.results.iter().map(|result| JsonResult { result, fields: &self.fields }).collect();
+        serde_json::to_writer_pretty(&mut self.out, &JsonDocument { results })?;
+        writeln!(self.out)
+    }
+}
+
+struct NdjsonWriter<W: Write> {
+    out: W,
+    fields: Vec<CertField>,
+}
+
+impl<W: Write> ResultWriter for NdjsonWriter<W> {
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, &JsonResult { result, fields: &self.fields })?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+/// Flat representation of a [CheckResult] as csv does not support nested values, with one column per selected
+/// certificate field between the chain and the error columns
+struct CsvRow<'a> {
+    result: &'a CheckResult,
+    fields: &'a [CertField],
+}
+
+fn format_rfc3339(date: OffsetDateTime) -> String {
+    date.format(&Rfc3339).unwrap_or_default()
+}
+
+impl Serialize for CsvRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let result = self.result;
+        let mut row = serializer.serialize_struct("CsvRow", 25 + self.fields.len())?;
+        row.serialize_field("target", &result.target)?;
+        row.serialize_field("port", &result.port)?;
+        row.serialize_field("service", &result.service)?;
+        row.serialize_field("checked_at", &format_rfc3339(result.checked_at))?;
+        row.serialize_field("remaining_days", &result.remaining_days)?;
+        row.serialize_field("severity", &result.severity)?;
+        row.serialize_field("state", result.state.label())?;
+        row.serialize_field("validation", &result.validation.as_ref().map(|v| v.status.label()))?;
+        row.serialize_field("validation_detail", &result.validation.as_ref().and_then(|v| v.detail.as_deref()))?;
+        row.serialize_field("chain_order", &result.chain_order.map(|order| order.label()))?;
+        // fewest remaining days of all certificates of the chain
+        row.serialize_field("chain_remaining_days", &result.chain.iter().map(|element| element.remaining_days).min())?;
+        for field in self.fields {
+            row.serialize_field(field.column(), &result.certificate.as_ref().map(|cert| field.value(cert)))?;
+        }
+        row.serialize_field("error_kind", &result.error.as_ref().map(|e| e.kind()))?;
+        row.serialize_field("error_message", &result.error.as_ref().map(|e| e.message()))?;
+        row.serialize_field("attempts", &result.attempts)?;
+        row.serialize_field("address", &result.address.map(|address| address.to_string()))?;
+        row.serialize_field("inconsistent_nodes", &result.inconsistent_nodes)?;
+        row.serialize_field("client_auth_requested", &result.client_auth.as_ref().map(|client_auth| client_auth.requested))?;
+        // CA names advertised by the server, separated by `; `
+        row.serialize_field("client_auth_ca_names", &result.client_auth.as_ref().map(|client_auth| client_auth.ca_names.join("; ")))?;
+        row.serialize_field("file", &result.file.as_ref().map(|file| file.display().to_string()))?;
+        row.serialize_field("alias", &result.alias)?;
+        // violated lint rules and the common name of the certificate, separated by `; `
+        row.serialize_field("lints", &result.lint_findings()
+            .map(|(certificate, finding)| format!("{} ({})", finding.rule, certificate.get_common_name()))
+            .collect::<Vec<_>>()
+            .join("; "))?;
+        // violated requirements and their policy, separated by `; `
+        row.serialize_field("policy_violations", &result.policy_violations.iter()
+            .map(|violation| format!("{} ({})", violation.requirement, violation.policy))
+            .collect::<Vec<_>>()
+            .join("; "))?;
+        // changes since the last run, separated by `; `
+        row.serialize_field("changes", &result.changes.iter()
+            .map(|change| change.event.label())
+            .collect::<Vec<_>>()
+            .join("; "))?;
+        row.serialize_field("pinned", &result.pinned)?;
+        row.end()
+    }
+}
+
+struct CsvWriter<W: Write> {
+    out: csv::Writer<W>,
+    fields: Vec<CertField>,
+}
+
+impl<W: Write> ResultWriter for CsvWriter<W> {
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        self.out.serialize(CsvRow { result, fields: &self.fields })?;
+        self.out.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::ValidationStatus;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+    const ROOT: &[u8] = include_bytes!("../testdata/pki/root.crt");
+    const INTERMEDIATE: &[u8] = include_bytes!("../testdata/pki/intermediate.crt");
+    const LOCALHOST: &[u8] = include_bytes!("../testdata/pki/localhost.crt");
+    const WEAK: &[u8] = include_bytes!("../testdata/pki/lint/weak.crt");
+
+    fn test_results() -> Vec<CheckResult> {
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "gitea.tschirky.ch", "service": "other" }"#).unwrap();
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from(GITEA_CERT)).map(|certificate| EndpointCertificate {
+            chain: vec![certificate.clone()],
+            certificate,
+            chain_order: ChainOrder::Ordered,
+            validation: Some(Validation {
+                status: ValidationStatus::MissingIntermediate,
+                detail: Some("invalid peer certificate: UnknownIssuer".to_owned()),
+            }),
+            client_auth: ClientAuth::default(),
+        });
+        let unreachable: Site = serde_json::from_str(r#"{ "targetFqn": "schludri.e3ag.ch", "service": "flup", "port": 8443 }"#).unwrap();
+        vec![
+            CheckResult::new(&site, cert),
+            CheckResult::new(&unreachable, Err(CertError::TargetNotReachable("Target schludri.e3ag.ch:8443 is unreachable".to_owned()))),
+        ]
+    }
+
+    fn chain_result(matched: &[u8], chain: &[&[u8]], chain_order: ChainOrder) -> CheckResult {
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "localhost", "service": "other",
+            "severityLevels": [ { "name": "warning", "days": 35300 } ] }"#).unwrap();
+        let cert = |pem: &[u8]| SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(pem).unwrap()).unwrap();
+        CheckResult::new(&site, Ok(EndpointCertificate {
+            certificate: cert(matched),
+            chain: chain.iter().map(|pem| cert(pem)).collect(),
+            chain_order,
+            validation: Some(Validation { status: ValidationStatus::Valid, detail: None }),
+            client_auth: ClientAuth::default(),
+        }))
+    }
+
+    #[test]
+    fn test_chain_state() {
+        // the intermediate expires after 35300 days, the end entity before
+        let result = chain_result(INTERMEDIATE, &[INTERMEDIATE, ROOT], ChainOrder::Ordered);
+        assert_eq!(result.state, State::Ok);
+        assert_eq!(result.expiring_chain_elements().count(), 0);
+
+        let result = chain_result(INTERMEDIATE, &[INTERMEDIATE, LOCALHOST], ChainOrder::Ordered);
+        assert_eq!(result.severity, None);
+        assert_eq!(result.state, State::Warning);
+        let expiring: Vec<&ChainElement> = result.expiring_chain_elements().collect();
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].certificate.get_common_name(), "localhost");
+
+        let result = chain_result(INTERMEDIATE, &[INTERMEDIATE, ROOT], ChainOrder::Misordered);
+        assert_eq!(result.state, State::Warning);
+    }
+
+    #[test]
+    fn test_lint_findings() {
+        let result = chain_result(WEAK, &[WEAK, INTERMEDIATE], ChainOrder::Ordered);
+        assert_eq!(result.state, State::Critical);
+        let rules: Vec<&str> = result.lint_findings().map(|(_, finding)| finding.rule).collect();
+        assert_eq!(rules, ["weak-key", "weak-signature", "missing-server-auth", "validity-too-long", "cn-not-in-san"]);
+        assert!(result.chain[1].lints.is_empty());
+
+        let mut site: Site = serde_json::from_str(r#"{ "targetFqn": "localhost", "service": "other",
+            "disabledLints": [ "weak-key", "weak-signature" ] }"#).unwrap();
+        let cert = SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(WEAK).unwrap()).unwrap();
+        let endpoint = EndpointCertificate {
+            certificate: cert.clone(),
+            chain: vec![cert],
+            chain_order: ChainOrder::Ordered,
+            validation: None,
+            client_auth: ClientAuth::default(),
+        };
+        let result = CheckResult::new(&site, Ok(endpoint.clone()));
+        assert_eq!(result.state, State::Warning);
+        assert_eq!(result.lint_findings().count(), 3);
+
+        site.disabled_lints.extend(["missing-server-auth", "validity-too-long", "cn-not-in-san"].map(str::to_owned));
+        assert_eq!(CheckResult::new(&site, Ok(endpoint)).state, State::Ok);
+
+        let mut writer = CsvWriter { out: csv::Writer::from_writer(Vec::new()), fields: Vec::new() };
+        render(&mut writer, &[chain_result(WEAK, &[WEAK, INTERMEDIATE], ChainOrder::Ordered)]);
+        let output = String::from_utf8(writer.out.into_inner().unwrap()).unwrap();
+        assert!(output.lines().nth(1).unwrap().ends_with(",weak-key (weak.test.cert-monitor.local); weak-signature (weak.test.cert-monitor.local); \
+            missing-server-auth (weak.test.cert-monitor.local); validity-too-long (weak.test.cert-monitor.local); \
+            cn-not-in-san (weak.test.cert-monitor.local),,,"));
+    }
+
+    #[test]
+    fn test_policy_violations() {
+        let mut result = chain_result(LOCALHOST, &[LOCALHOST, INTERMEDIATE], ChainOrder::Ordered);
+        let internal: Policy = serde_json::from_str(r#"{ "keyAlgorithms": [ "EC P-256" ], "requiredSans": [ "localhost" ] }"#).unwrap();
+        result.apply_policies(&[("internal", &internal)]);
+        assert!(result.policy_violations.is_empty());
+        assert_eq!(result.state, State::Warning);
+
+        let payment: Policy = serde_json::from_str(r#"{ "wildcardsAllowed": false, "state": "critical" }"#).unwrap();
+        result.apply_policies(&[("internal", &internal), ("payment", &payment)]);
+        assert_eq!(result.state, State::Critical);
+        let mut writer = CsvWriter { out: csv::Writer::from_writer(Vec::new()), fields: Vec::new() };
+        render(&mut writer, &[result]);
+        let output = String::from_utf8(writer.out.into_inner().unwrap()).unwrap();
+        assert!(output.lines().nth(1).unwrap().ends_with(",validity-too-long (localhost),wildcardsAllowed (payment),,"));
+
+        let mut failed = test_results().remove(1);
+        failed.apply_policies(&[("payment", &payment)]);
+        assert!(failed.policy_violations.is_empty());
+    }
+
+    #[test]
+    fn test_pins() {
+        let pinned = |pins: &str| {
+            let site: Site = serde_json::from_str(&format!(r#"{{ "targetFqn": "localhost", "service": "other",
+                "disabledLints": [ "validity-too-long" ], "pins": [ {} ] }}"#, pins)).unwrap();
+            let cert = |pem: &[u8]| SimpleCertificate::from_certificate_der(&CertificateDer::from_pem_slice(pem).unwrap()).unwrap();
+            CheckResult::new(&site, Ok(EndpointCertificate {
+                certificate: cert(LOCALHOST),
+                chain: vec![cert(LOCALHOST), cert(INTERMEDIATE)],
+                chain_order: ChainOrder::Ordered,
+                validation: None,
+                client_auth: ClientAuth::default(),
+            }))
+        };
+        // the key of the intermediate, in the notation of curl's --pinnedpubkey
+        let result = pinned(r#""sha256//Ogxk8WSHuZAFPfLtCQv/OBMeuqStz1q99PEerdc8RMo=""#);
+        assert_eq!((result.pinned, result.state), (Some(true), State::Ok));
+        // the key of the root, which the server does not present
+        let result = pinned(r#""sha256/qruCJwUMCk9LpaFRQS2ghw2l/Niqr4nsf1pRqQc/oKU=""#);
+        assert_eq!((result.pinned, result.state), (Some(false), State::Critical));
+        assert_eq!(pinned("").pinned, None);
+
+        let mut writer = CsvWriter { out: csv::Writer::from_writer(Vec::new()), fields: Vec::new() };
+        render(&mut writer, &[result]);
+        let output = String::from_utf8(writer.out.into_inner().unwrap()).unwrap();
+        assert!(output.lines().nth(1).unwrap().ends_with(",,,false"));
+    }
+
+    #[test]
+    fn test_node_consistency() {
+        let mut results = vec![
+            chain_result(LOCALHOST, &[LOCALHOST, INTERMEDIATE], ChainOrder::Ordered),
+            chain_result(LOCALHOST, &[LOCALHOST, INTERMEDIATE], ChainOrder::Ordered),
+        ];
+        CheckResult::check_node_consistency(&mut results);
+        assert!(results.iter().all(|result| !result.inconsistent_nodes));
+
+        results.push(chain_result(INTERMEDIATE, &[INTERMEDIATE, ROOT], ChainOrder::Ordered));
+        results[2].address = Some("10.0.0.3".parse().unwrap());
+        CheckResult::check_node_consistency(&mut results);
+        assert!(results.iter().all(|result| result.inconsistent_nodes && result.state >= State::Warning));
+        assert_eq!(results[2].endpoint(), "localhost:443 (10.0.0.3)");
+    }
+
+    #[test]
+    fn test_pem_chain_output() {
+        let result = chain_result(LOCALHOST, &[LOCALHOST, INTERMEDIATE], ChainOrder::Ordered);
+        let mut writer = PemWriter { out: Vec::new(), full_chain: true };
+        render(&mut writer, std::slice::from_ref(&result));
+        let certs: Vec<CertificateDer> = CertificateDer::pem_slice_iter(&writer.out).map(|cert| cert.unwrap()).collect();
+        assert_eq!(certs, vec![CertificateDer::from_pem_slice(LOCALHOST).unwrap(), CertificateDer::from_pem_slice(INTERMEDIATE).unwrap()]);
+
+        let mut writer = PemWriter { out: Vec::new(), full_chain: false };
+        render(&mut writer, &[result]);
+        assert_eq!(CertificateDer::pem_slice_iter(&writer.out).count(), 1);
+    }
+
+    fn render<W: ResultWriter>(writer: &mut W, results: &[CheckResult]) {
+        writer.begin().unwrap();
+        results.iter().for_each(|result| writer.write_result(result).unwrap());
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_json_output() {
+        let mut writer = JsonWriter { out: Vec::new(), results: Vec::new(), fields: Vec::new() };
+        render(&mut writer, &test_results());
+        let document: serde_json::Value = serde_json::from_slice(&writer.out).unwrap();
+
+        let ok = &document["results"][0];
+        assert_eq!(ok["target"], "gitea.tschirky.ch");
+        assert_eq!(ok["port"], 443);
+        assert_eq!(ok["certificate"]["commonName"], "gitea.tschirky.ch");
+        assert_eq!(ok["certificate"]["serialNumber"], "04ba66ac8f777d7daa73e89ceab53b47f5ae");
+        assert_eq!(ok["certificate"]["sanDnsNames"][0], "gitea.tschirky.ch");
+        assert_eq!(ok["certificate"]["isCa"], false);
+        assert_eq!(ok["state"], "CRITICAL");
+        assert_eq!(ok["severity"], "critical");
+        assert_eq!(ok["validation"]["status"], "MissingIntermediate");
+        assert_eq!(ok["chainOrder"], "Ordered");
+        assert_eq!(ok["chain"][0]["certificate"]["commonName"], "gitea.tschirky.ch");
+        assert_eq!(ok["chain"][0]["severity"], "critical");
+        assert!(ok["error"].is_null());
+
+        let failed = &document["results"][1];
+        assert!(failed["certificate"].is_null());
+        assert_eq!(failed["chain"].as_array().unwrap().len(), 0);
+        assert_eq!(failed["error"]["kind"], "TargetNotReachable");
+        assert_eq!(failed["error"]["message"], "Target schludri.e3ag.ch:8443 is unreachable");
+    }
+
+    #[test]
+    fn test_ndjson_output() {
+        let mut writer = NdjsonWriter { out: Vec::new(), fields: Vec::new() };
+        render(&mut writer, &test_results());
+        let output = String::from_utf8(writer.out).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["target"], "schludri.e3ag.ch");
+        assert_eq!(second["error"]["kind"], "TargetNotReachable");
+    }
+
+    #[test]
+    fn test_csv_output() {
+        let mut writer = CsvWriter { out: csv::Writer::from_writer(Vec::new()), fields: CSV_FIELDS.to_vec() };
+        render(&mut writer, &test_results());
+        let output = String::from_utf8(writer.out.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "target,port,service,checked_at,remaining_days,severity,state,validation,validation_detail,chain_order,chain_remaining_days,expiration_date,serial_number,common_name,san_dns_names,san_ip_addresses,is_ca,error_kind,error_message,attempts,address,inconsistent_nodes,client_auth_requested,client_auth_ca_names,file,alias,lints,policy_violations,changes,pinned");
+        assert!(lines[1].starts_with("gitea.tschirky.ch,443,other,"));
+        assert!(lines[1].ends_with(",04ba66ac8f777d7daa73e89ceab53b47f5ae,gitea.tschirky.ch,gitea.tschirky.ch,,false,,,1,,false,false,,,,,,,"));
+        assert!(lines[2].ends_with(",,,CRITICAL,,,,,,,,,,,TargetNotReachable,Target schludri.e3ag.ch:8443 is unreachable,1,,false,,,,,,,,"));
+    }
+
+    #[test]
+    fn test_selected_fields() {
+        let fields = [CertField::Issuer, CertField::PublicKey, CertField::Sha256, CertField::Ocsp];
+
+        let mut writer = CsvWriter { out: csv::Writer::from_writer(Vec::new()), fields: fields.to_vec() };
+        render(&mut writer, &test_results());
+        let output = String::from_utf8(writer.out.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].contains(",chain_remaining_days,issuer,public_key,sha256_fingerprint,ocsp_urls,error_kind,"));
+        assert!(lines[1].contains(",\"C=US, O=Let's Encrypt, CN=R11\",RSA 2048,85c4d16c8415ef4e4563acbcb3f69975647fcc779f9007eba2d3b20f0557b001,http://r11.o.lencr.org,,"));
+
+        let mut writer = NdjsonWriter { out: Vec::new(), fields: fields.to_vec() };
+        render(&mut writer, &test_results());
+        let first: serde_json::Value = serde_json::from_str(String::from_utf8(writer.out).unwrap().lines().next().unwrap()).unwrap();
+        let properties: Vec<&String> = first["certificate"].as_object().unwrap().keys().collect();
+        assert_eq!(properties, ["issuer", "ocspUrls", "publicKey", "sha256Fingerprint"]);
+        assert_eq!(first["certificate"]["publicKey"]["size"], 2048);
+        assert_eq!(first["chain"][0]["certificate"].as_object().unwrap().len(), 4);
+
+        let mut writer = TableWriter { out: Vec::new(), fields: vec![CertField::Cn, CertField::KeyUsage] };
+        render(&mut writer, &test_results());
+        let output = console::strip_ansi_codes(std::str::from_utf8(&writer.out).unwrap()).into_owned();
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].ends_with("| CN                                                 | Key Usage                          "));
+        assert!(lines[2].contains("| gitea.tschirky.ch                                  | digitalSignature keyEncipherment   "));
+        assert!(lines[3].contains("| <No CN>                                            | <TargetNotReachable : Target schludri.e3ag.ch:8443 is unreachable>"));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::cert_retriever::SimpleCertificate;
+use crate::nagios::State;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+fn default_policy_state() -> State { State::Warning }
+
+fn default_wildcards_allowed() -> bool { true }
+
+/// Organisation-wide requirements on the end entity certificate of the sites it is attached to, every
+/// requirement which is not configured is met by any certificate
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Policy {
+    /// Sites with any of these tags are checked against the policy, as are the sites naming it in `policies`
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// State the site is raised to if the certificate violates the policy
+    #[serde(default = "default_policy_state")]
+    pub state: State,
+
+    /// Names of which the issuer distinguished name must contain one, e.g. `Let's Encrypt`, compared
+    /// case-insensitively
+    #[serde(default)]
+    pub allowed_issuers: Vec<String>,
+
+    /// Allowed public keys as algorithm, e.g. `RSA`, or as algorithm and curve, e.g. `EC P-256`
+    #[serde(default)]
+    pub key_algorithms: Vec<String>,
+
+    /// Smallest key size in bits per algorithm, e.g. `{ "RSA": 3072 }`
+    #[serde(default)]
+    pub min_key_size: BTreeMap<String, usize>,
+
+    /// Whether the common name and the subject alternative names may contain wildcards
+    #[serde(default = "default_wildcards_allowed")]
+    pub wildcards_allowed: bool,
+
+    /// Longest allowed time between not before and not after
+    #[serde(default)]
+    pub max_lifetime_days: Option<i64>,
+
+    /// DNS names and IP addresses which must all be subject alternative names of the certificate
+    #[serde(default)]
+    pub required_sans: Vec<String>,
+}
+
+/// Requirement of a policy which the certificate of a site does not meet
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyViolation {
+    /// Name of the policy in `policies`
+    pub policy: String,
+    /// The violated requirement, named as in the config, e.g. `maxLifetimeDays`
+    pub requirement: &'static str,
+    pub state: State,
+    pub message: String,
+}
+
+impl Policy {
+    /// Whether the policy applies to a site with the given tags
+    pub fn matches_tags(&self, tags: &[String]) -> bool {
+        self.tags.iter().any(|tag| tags.contains(tag))
+    }
+
+    /// All requirements of the policy named `name` which `cert` violates
+    pub fn check(&self, name: &str, cert: &SimpleCertificate) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        let mut violate = |requirement: &'static str, message: String| violations.push(PolicyViolation {
+            policy: name.to_owned(),
+            requirement,
+            state: self.state,
+            message,
+        });
+
+        let issuer = cert.get_issuer().to_lowercase();
+        if !self.allowed_issuers.is_empty() && !self.allowed_issuers.iter().any(|allowed| issuer.contains(&allowed.to_lowercase())) {
+            violate("allowedIssuers", format!("Issuer {} is not allowed", cert.get_issuer()));
+        }
+
+        let key = cert.get_public_key();
+        let key_name = key.to_string();
+        if !self.key_algorithms.is_empty() && !self.key_algorithms.iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&key.algorithm) || allowed.eq_ignore_ascii_case(&key_name)) {
+            violate("keyAlgorithms", format!("{} key is not allowed", key_name));
+        }
+        let min_size = self.min_key_size.iter()
+            .find(|(algorithm, _)| algorithm.eq_ignore_ascii_case(&key.algorithm))
+            .map(|(_, size)| *size);
+        if let (Some(min_size), Some(size)) = (min_size, key.size) {
+            if size < min_size {
+                violate("minKeySize", format!("{} key of {} bits is shorter than {} bits", key.algorithm, size, min_size));
+            }
+        }
+
+        if !self.wildcards_allowed {
+            let wildcard = std::iter::once(cert.get_common_name())
+                .chain(cert.get_san_dns_names().iter().map(String::as_str))
+                .find(|name| name.starts_with('*'));
+            if let Some(wildcard) = wildcard {
+                violate("wildcardsAllowed", format!("Wildcard {} is not allowed", wildcard));
+            }
+        }
+
+        let lifetime_days = (cert.get_expiration_date() - cert.get_not_before()).whole_days();
+        if let Some(max_days) = self.max_lifetime_days.filter(|max_days| lifetime_days > *max_days) {
+            violate("maxLifetimeDays", format!("Lifetime of {} days exceeds {} days", lifetime_days, max_days));
+        }
+
+        let missing: Vec<&str> = self.required_sans.iter()
+            .filter(|required| !has_san(cert, required))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            violate("requiredSans", format!("Subject alternative names {} are missing", missing.join(", ")));
+        }
+        violations
+    }
+}
+
+fn has_san(cert: &SimpleCertificate, name: &str) -> bool {
+    match name.parse() {
+        Ok(ip) => cert.get_san_ip_addresses().contains(&ip),
+        Err(_) => cert.get_san_dns_names().iter().any(|san| san.eq_ignore_ascii_case(name)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+    const LOCALHOST_CERT: &[u8] = include_bytes!("../testdata/pki/localhost.crt");
+
+    fn certificate(data: &[u8]) -> SimpleCertificate {
+        let der = CertificateDer::from_pem_slice(data).unwrap_or_else(|_| CertificateDer::from(data));
+        SimpleCertificate::from_certificate_der(&der).unwrap()
+    }
+
+    fn policy(json: &str) -> Policy {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// Violated requirements and their messages
+    fn violations(policy: &Policy, data: &[u8]) -> Vec<(&'static str, String)> {
+        policy.check("public", &certificate(data)).into_iter()
+            .map(|violation| (violation.requirement, violation.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let policy = policy("{}");
+        assert_eq!(policy.state, State::Warning);
+        assert!(policy.wildcards_allowed);
+        assert!(violations(&policy, GITEA_CERT).is_empty());
+        assert!(violations(&policy, LOCALHOST_CERT).is_empty());
+    }
+
+    #[test]
+    fn test_public_policy() {
+        let public = policy(r#"{ "state": "critical", "allowedIssuers": [ "Sectigo", "let's encrypt" ],
+            "keyAlgorithms": [ "EC P-256", "RSA" ], "minKeySize": { "RSA": 3072 }, "wildcardsAllowed": false,
+            "maxLifetimeDays": 90, "requiredSans": [ "gitea.tschirky.ch" ] }"#);
+        assert_eq!(violations(&public, GITEA_CERT), [
+            ("minKeySize", "RSA key of 2048 bits is shorter than 3072 bits".to_owned()),
+        ]);
+        assert_eq!(violations(&public, LOCALHOST_CERT), [
+            ("allowedIssuers", "Issuer O=Cert Monitor Test, CN=Cert Monitor Test Intermediate CA is not allowed".to_owned()),
+            ("wildcardsAllowed", "Wildcard *.test.cert-monitor.local is not allowed".to_owned()),
+            ("maxLifetimeDays", "Lifetime of 35000 days exceeds 90 days".to_owned()),
+            ("requiredSans", "Subject alternative names gitea.tschirky.ch are missing".to_owned()),
+        ]);
+        assert!(public.check("public", &certificate(GITEA_CERT)).iter().all(|violation| violation.state == State::Critical));
+    }
+
+    #[test]
+    fn test_key_and_sans() {
+        let policy = policy(r#"{ "keyAlgorithms": [ "Ed25519", "EC P-384" ], "requiredSans": [ "localhost", "127.0.0.1", "::2" ] }"#);
+        assert_eq!(violations(&policy, LOCALHOST_CERT), [
+            ("keyAlgorithms", "EC P-256 key is not allowed".to_owned()),
+            ("requiredSans", "Subject alternative names ::2 are missing".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_tags() {
+        let policy = policy(r#"{ "tags": [ "payment", "public" ] }"#);
+        assert!(policy.matches_tags(&["internal".to_owned(), "payment".to_owned()]));
+        assert!(!policy.matches_tags(&["internal".to_owned()]));
+        assert!(!policy.matches_tags(&[]));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config::Site;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+
+/// Upper bounds of the checks running at the same time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parallelism {
+    pub workers: usize,
+    /// Checks of sites with the same target host, so that a single load balancer is not hammered
+    pub per_host: usize,
+}
+
+fn host_key(site: &Site) -> String {
+    site.target_fqn.to_ascii_lowercase()
+}
+
+struct QueueState {
+    pending: VecDeque<usize>,
+    active_per_host: HashMap<String, usize>,
+}
+
+/// Hands out the sites in config order, skipping the ones whose host is already checked `per_host` times
+struct HostQueue<'a> {
+    sites: &'a [&'a Site],
+    per_host: usize,
+    state: Mutex<QueueState>,
+    slot_freed: Condvar,
+}
+
+impl<'a> HostQueue<'a> {
+    fn new(sites: &'a [&'a Site], per_host: usize) -> HostQueue<'a> {
+        HostQueue {
+            sites,
+            per_host: per_host.max(1),
+            state: Mutex::new(QueueState {
+                pending: (0..sites.len()).collect(),
+                active_per_host: HashMap::new(),
+            }),
+            slot_freed: Condvar::new(),
+        }
+    }
+
+    /// Index of the next site to check, waits while all pending sites are at their host limit.
+    /// None once all sites are handed out.
+    fn take(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if state.pending.is_empty() {
+                return None;
+            }
+            let available = state.pending.iter().position(|&idx| {
+                state.active_per_host.get(&host_key(self.sites[idx])).copied().unwrap_or(0) < self.per_host
+            });
+            if let Some(position) = available {
+                let idx = state.pending.remove(position)?;
+                *state.active_per_host.entry(host_key(self.sites[idx])).or_insert(0) += 1;
+                return Some(idx);
+            }
+            state = self.slot_freed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn done(&self, idx: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(active) = state.active_per_host.get_mut(&host_key(self.sites[idx])) {
+            *active = active.saturating_sub(1);
+        }
+        self.slot_freed.notify_all();
+    }
+}
+
+/// Runs `check` for all sites on up to `parallelism.workers` threads and passes the results to `emit`
+/// on the calling thread, in the order of `sites` regardless of the order in which the checks complete.
+pub fn check_in_order<T, C, E>(sites: &[&Site], parallelism: Parallelism, check: C, mut emit: E)
+where
+    T: Send,
+    C: Fn(&Site) -> T + Sync,
+    E: FnMut(&Site, T),
+{
+    let queue = HostQueue::new(sites, parallelism.per_host);
+    let (result_tx, result_rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..parallelism.workers.clamp(1, sites.len().max(1)) {
+            let result_tx = result_tx.clone();
+            let queue = &queue;
+            let check = &check;
+            scope.spawn(move || {
+                while let Some(idx) = queue.take() {
+                    let result = check(sites[idx]);
+                    queue.done(idx);
+                    if result_tx.send((idx, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_tx);
+
+        // results which completed before the ones preceding them in the config
+        let mut completed = BTreeMap::new();
+        let mut next = 0;
+        for (idx, result) in result_rx {
+            completed.insert(idx, result);
+            while let Some(result) = completed.remove(&next) {
+                emit(sites[next], result);
+                next += 1;
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn sites() -> Vec<Site> {
+        ["lb.tschirky.ch", "www.tschirky.ch", "LB.tschirky.ch", "gitea.tschirky.ch", "lb.tschirky.ch", "owncloud.tschirky.ch"]
+            .iter()
+            .enumerate()
+            .map(|(idx, host)| Site::new(host, &format!("service{}", idx), 443))
+            .collect()
+    }
+
+    #[test]
+    fn test_results_in_config_order() {
+        let sites = sites();
+        let site_refs: Vec<&Site> = sites.iter().collect();
+        let mut emitted = vec![];
+        check_in_order(&site_refs, Parallelism { workers: 4, per_host: 2 }, |site| {
+            // the first sites take longest
+            let idx: u64 = site.service.trim_start_matches("service").parse().unwrap();
+            thread::sleep(Duration::from_millis(60 - idx * 10));
+            site.service.clone()
+        }, |site, service| {
+            assert_eq!(site.service, service);
+            emitted.push(service);
+        });
+        assert_eq!(emitted, ["service0", "service1", "service2", "service3", "service4", "service5"]);
+    }
+
+    #[test]
+    fn test_per_host_limit() {
+        let sites = sites();
+        let site_refs: Vec<&Site> = sites.iter().collect();
+        let active_lb = AtomicUsize::new(0);
+        let max_active_lb = AtomicUsize::new(0);
+        let active = AtomicUsize::new(0);
+        let max_active = AtomicUsize::new(0);
+        check_in_order(&site_refs, Parallelism { workers: 3, per_host: 1 }, |site| {
+            let is_lb = site.target_fqn.eq_ignore_ascii_case("lb.tschirky.ch");
+            if is_lb {
+                max_active_lb.fetch_max(active_lb.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            }
+            max_active.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            active.fetch_sub(1, Ordering::SeqCst);
+            if is_lb {
+                active_lb.fetch_sub(1, Ordering::SeqCst);
+            }
+        }, |_, _| {});
+        assert_eq!(max_active_lb.load(Ordering::SeqCst), 1);
+        assert!(max_active.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn test_no_sites() {
+        let mut emitted = 0;
+        check_in_order(&[], Parallelism { workers: 4, per_host: 1 }, |_| (), |_, _| emitted += 1);
+        assert_eq!(emitted, 0);
+    }
+}
@@ -0,0 +1,274 @@
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use x509_parser::extensions::{DistributionPointName, GeneralName, ParsedExtension};
+use x509_parser::objects::{oid2sn, oid_registry};
+use x509_parser::oid_registry::{Oid, OID_EC_P256, OID_KEY_TYPE_DSA, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_NIST_EC_P384, OID_NIST_EC_P521,
+                                OID_PKCS1_RSAENCRYPTION, OID_PKCS1_RSASSAPSS, OID_PKIX_ACCESS_DESCRIPTOR_CA_ISSUERS,
+                                OID_PKIX_ACCESS_DESCRIPTOR_OCSP, OID_SIG_ED25519, OID_SIG_ED448};
+use x509_parser::prelude::X509Certificate;
+
+/// Algorithm and strength of the subject public key
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PublicKeyInfo {
+    /// `RSA`, `EC`, `Ed25519`, ... or the OID of other algorithms
+    pub algorithm: String,
+    /// Size in bits if known, e.g. the modulus of an RSA key or the field of an EC key
+    pub size: Option<usize>,
+    /// NIST name of the curve of an EC key, e.g. `P-256`
+    pub curve: Option<String>,
+}
+
+impl Display for PublicKeyInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.curve, self.size) {
+            (Some(curve), _) => write!(f, "{} {}", self.algorithm, curve),
+            (None, Some(size)) => write!(f, "{} {}", self.algorithm, size),
+            (None, None) => write!(f, "{}", self.algorithm),
+        }
+    }
+}
+
+/// OpenSSL names of legacy signature algorithms missing in the OID registry
+const LEGACY_SIGNATURE_ALGORITHMS: &[(&str, &str)] = &[
+    ("1.2.840.10045.4.1", "ecdsa-with-SHA1"),
+    ("1.3.14.3.2.29", "sha1WithRSA"),
+];
+
+/// Short name of the OID as used by OpenSSL, e.g. `sha256WithRSAEncryption`, or its dotted form if unknown
+pub fn oid_name(oid: &Oid) -> String {
+    let id = oid.to_id_string();
+    match LEGACY_SIGNATURE_ALGORITHMS.iter().find(|(legacy, _)| *legacy == id) {
+        Some((_, name)) => (*name).to_owned(),
+        None => oid2sn(oid, oid_registry()).map_or(id, |name| name.to_owned()),
+    }
+}
+
+pub fn get_signature_algorithm(cert: &X509Certificate) -> String {
+    oid_name(&cert.signature_algorithm.algorithm)
+}
+
+pub fn get_public_key(cert: &X509Certificate) -> PublicKeyInfo {
+    let spki = cert.public_key();
+    let oid = &spki.algorithm.algorithm;
+    let algorithm = match oid {
+        oid if *oid == OID_PKCS1_RSAENCRYPTION => "RSA".to_owned(),
+        oid if *oid == OID_PKCS1_RSASSAPSS => "RSASSA-PSS".to_owned(),
+        oid if *oid == OID_KEY_TYPE_EC_PUBLIC_KEY => "EC".to_owned(),
+        oid if *oid == OID_SIG_ED25519 => "Ed25519".to_owned(),
+        oid if *oid == OID_SIG_ED448 => "Ed448".to_owned(),
+        oid if *oid == OID_KEY_TYPE_DSA => "DSA".to_owned(),
+        oid => oid_name(oid),
+    };
+    let curve = spki.algorithm.parameters.as_ref()
+        .filter(|_| *oid == OID_KEY_TYPE_EC_PUBLIC_KEY)
+        .and_then(|parameters| parameters.as_oid().ok())
+        .map(|curve| match curve {
+            curve if curve == OID_EC_P256 => "P-256".to_owned(),
+            curve if curve == OID_NIST_EC_P384 => "P-384".to_owned(),
+            curve if curve == OID_NIST_EC_P521 => "P-521".to_owned(),
+            curve => oid_name(&curve),
+        });
+    let size = spki.parsed().ok()
+        .map(|key| key.key_size())
+        .filter(|size| *size > 0);
+    PublicKeyInfo { algorithm, size, curve }
+}
+
+/// Key usages named as in RFC 5280, e.g. `digitalSignature`
+pub fn get_key_usage(cert: &X509Certificate) -> Vec<String> {
+    let Ok(Some(key_usage)) = cert.key_usage() else {
+        return Vec::new();
+    };
+    let usage = key_usage.value;
+    [
+        (usage.digital_signature(), "digitalSignature"),
+        (usage.non_repudiation(), "nonRepudiation"),
+        (usage.key_encipherment(), "keyEncipherment"),
+        (usage.data_encipherment(), "dataEncipherment"),
+        (usage.key_agreement(), "keyAgreement"),
+        (usage.key_cert_sign(), "keyCertSign"),
+        (usage.crl_sign(), "cRLSign"),
+        (usage.encipher_only(), "encipherOnly"),
+        (usage.decipher_only(), "decipherOnly"),
+    ].into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name.to_owned())
+        .collect()
+}
+
+/// Extended key usages named as in RFC 5280, e.g. `serverAuth`, other purposes by their OID
+pub fn get_extended_key_usage(cert: &X509Certificate) -> Vec<String> {
+    let Ok(Some(eku)) = cert.extended_key_usage() else {
+        return Vec::new();
+    };
+    let eku = eku.value;
+    let mut usages: Vec<String> = [
+        (eku.any, "anyExtendedKeyUsage"),
+        (eku.server_auth, "serverAuth"),
+        (eku.client_auth, "clientAuth"),
+        (eku.code_signing, "codeSigning"),
+        (eku.email_protection, "emailProtection"),
+        (eku.time_stamping, "timeStamping"),
+        (eku.ocsp_signing, "OCSPSigning"),
+    ].into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name.to_owned())
+        .collect();
+    usages.extend(eku.other.iter().map(|oid| oid.to_id_string()));
+    usages
+}
+
+/// Lower case hex SHA-1 digest of the DER encoded certificate
+pub fn sha1_fingerprint(der: &[u8]) -> String {
+    data_encoding::HEXLOWER.encode(&Sha1::digest(der))
+}
+
+/// Lower case hex SHA-256 digest of the DER encoded certificate
+pub fn sha256_fingerprint(der: &[u8]) -> String {
+    data_encoding::HEXLOWER.encode(&Sha256::digest(der))
+}
+
+/// Base64 SHA-256 digest of the DER encoded subject public key info, as used for key pinning
+pub fn spki_sha256(cert: &X509Certificate) -> String {
+    data_encoding::BASE64.encode(&Sha256::digest(cert.public_key().raw))
+}
+
+fn parsed_extensions<'a>(cert: &'a X509Certificate) -> impl Iterator<Item=&'a ParsedExtension<'a>> {
+    cert.extensions().iter().map(|extension| extension.parsed_extension())
+}
+
+pub fn get_subject_key_id(cert: &X509Certificate) -> Option<String> {
+    parsed_extensions(cert).find_map(|extension| match extension {
+        ParsedExtension::SubjectKeyIdentifier(key_id) => Some(data_encoding::HEXLOWER.encode(key_id.0)),
+        _ => None,
+    })
+}
+
+pub fn get_authority_key_id(cert: &X509Certificate) -> Option<String> {
+    parsed_extensions(cert).find_map(|extension| match extension {
+        ParsedExtension::AuthorityKeyIdentifier(aki) => aki.key_identifier.as_ref()
+            .map(|key_id| data_encoding::HEXLOWER.encode(key_id.0)),
+        _ => None,
+    })
+}
+
+fn uris<'a>(names: impl IntoIterator<Item=&'a GeneralName<'a>>) -> Vec<String> {
+    names.into_iter()
+        .filter_map(|name| match name {
+            GeneralName::URI(uri) => Some(uri.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// URLs of the authority information access extension with the access method `method`
+fn get_access_urls(cert: &X509Certificate, method: &Oid) -> Vec<String> {
+    parsed_extensions(cert)
+        .filter_map(|extension| match extension {
+            ParsedExtension::AuthorityInfoAccess(aia) => Some(aia),
+            _ => None,
+        })
+        .flat_map(|aia| uris(aia.accessdescs.iter()
+            .filter(|description| description.access_method == *method)
+            .map(|description| &description.access_location)))
+        .collect()
+}
+
+pub fn get_ocsp_urls(cert: &X509Certificate) -> Vec<String> {
+    get_access_urls(cert, &OID_PKIX_ACCESS_DESCRIPTOR_OCSP)
+}
+
+pub fn get_ca_issuers_urls(cert: &X509Certificate) -> Vec<String> {
+    get_access_urls(cert, &OID_PKIX_ACCESS_DESCRIPTOR_CA_ISSUERS)
+}
+
+/// URLs of the full names of the CRL distribution points
+pub fn get_crl_distribution_points(cert: &X509Certificate) -> Vec<String> {
+    parsed_extensions(cert)
+        .filter_map(|extension| match extension {
+            ParsedExtension::CRLDistributionPoints(points) => Some(points),
+            _ => None,
+        })
+        .flat_map(|points| points.iter())
+        .filter_map(|point| match &point.distribution_point {
+            Some(DistributionPointName::FullName(names)) => Some(uris(names)),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+pub fn get_policy_oids(cert: &X509Certificate) -> Vec<String> {
+    parsed_extensions(cert)
+        .filter_map(|extension| match extension {
+            ParsedExtension::CertificatePolicies(policies) => Some(policies),
+            _ => None,
+        })
+        .flat_map(|policies| policies.iter().map(|policy| policy.policy_id.to_id_string()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+    use x509_parser::prelude::FromDer;
+
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+    const PROFILE_CERT: &[u8] = include_bytes!("../testdata/pki/profile.crt");
+    const ROOT_CERT: &[u8] = include_bytes!("../testdata/pki/root.crt");
+
+    /// Calls `f` with the parsed PEM or DER certificate and its DER encoding
+    fn with_certificate<T>(data: &[u8], f: impl FnOnce(&X509Certificate, &[u8]) -> T) -> T {
+        let der = CertificateDer::from_pem_slice(data).unwrap_or_else(|_| CertificateDer::from(data));
+        let (_, cert) = X509Certificate::from_der(der.as_ref()).unwrap();
+        f(&cert, der.as_ref())
+    }
+
+    #[test]
+    fn test_profile_extensions() {
+        with_certificate(PROFILE_CERT, |cert, der| {
+            assert_eq!(get_signature_algorithm(cert), "ecdsa-with-SHA384");
+            assert_eq!(get_public_key(cert).to_string(), "EC P-384");
+            assert_eq!(get_public_key(cert).size, Some(384));
+            assert_eq!(get_key_usage(cert), ["digitalSignature", "keyAgreement"]);
+            assert_eq!(get_extended_key_usage(cert), ["serverAuth", "clientAuth"]);
+            assert_eq!(sha1_fingerprint(der), "74fa8a5b2f7b827ab8ec0a24782b9790d8c0ce8d");
+            assert_eq!(sha256_fingerprint(der), "7b7041eff2eb728b69f308c3a950f1f08ff197cb133f93180301fcb0d2db6b44");
+            assert_eq!(spki_sha256(cert), "KGhaiXKmHuplrklPUuAwTrAjrccrTn7k6m78GAFh0mk=");
+            assert_eq!(get_subject_key_id(cert).as_deref(), Some("4eeb924326165878d248623aa5be9a8c23997255"));
+            assert_eq!(get_authority_key_id(cert).as_deref(), Some("c1921a3da6ef604e7938215ef3d4f5b0f0f1a678"));
+            assert_eq!(get_ocsp_urls(cert), ["http://ocsp.test.cert-monitor.local"]);
+            assert_eq!(get_ca_issuers_urls(cert), ["http://ca.test.cert-monitor.local/intermediate.crt"]);
+            assert_eq!(get_crl_distribution_points(cert), ["http://ca.test.cert-monitor.local/intermediate.crl"]);
+            assert_eq!(get_policy_oids(cert), ["2.23.140.1.2.2", "1.3.6.1.4.1.99999.1"]);
+        });
+    }
+
+    #[test]
+    fn test_rsa_certificate() {
+        with_certificate(GITEA_CERT, |cert, _| {
+            assert_eq!(get_signature_algorithm(cert), "sha256WithRSAEncryption");
+            assert_eq!(get_public_key(cert), PublicKeyInfo { algorithm: "RSA".to_owned(), size: Some(2048), curve: None });
+            assert_eq!(get_key_usage(cert), ["digitalSignature", "keyEncipherment"]);
+            assert_eq!(get_ocsp_urls(cert), ["http://r11.o.lencr.org"]);
+            assert!(get_crl_distribution_points(cert).is_empty());
+        });
+    }
+
+    #[test]
+    fn test_missing_extensions() {
+        with_certificate(ROOT_CERT, |cert, _| {
+            assert_eq!(get_key_usage(cert), ["keyCertSign", "cRLSign"]);
+            assert!(get_extended_key_usage(cert).is_empty());
+            assert!(get_authority_key_id(cert).is_none());
+            assert!(get_ocsp_urls(cert).is_empty());
+            assert!(get_policy_oids(cert).is_empty());
+        });
+    }
+}
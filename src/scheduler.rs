@@ -0,0 +1,119 @@
+use crate::config::{Site, SiteConfig};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const SECONDS_PER_HOUR: u64 = 3600;
+
+/// Re-checks every site of a [SiteConfig] on its own interval until shutdown is requested.
+pub struct Scheduler<'a> {
+    entries: Vec<ScheduledSite<'a>>,
+}
+
+struct ScheduledSite<'a> {
+    site: &'a Site,
+    interval: Duration,
+    next_run: Instant,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(site_config: &'a SiteConfig, default_interval_hours: u32) -> Scheduler<'a> {
+        Self::with_rng(site_config, default_interval_hours, fastrand::Rng::new())
+    }
+
+    fn with_rng(site_config: &'a SiteConfig, default_interval_hours: u32, mut rng: fastrand::Rng) -> Scheduler<'a> {
+        let now = Instant::now();
+        let entries = site_config.site_iter().map(|site| {
+            let hours = site.interval_hours.unwrap_or(default_interval_hours).max(1);
+            let interval = Duration::from_secs(hours as u64 * SECONDS_PER_HOUR);
+            // spread the first checks so that not all sites hit the network at once
+            let jitter = site_config.jitter_seconds.min(interval.as_secs());
+            ScheduledSite {
+                site,
+                interval,
+                next_run: now + Duration::from_secs(rng.u64(0..=jitter)),
+            }
+        }).collect();
+        Scheduler { entries }
+    }
+
+    fn next_run(&self) -> Option<Instant> {
+        self.entries.iter()
+            .map(|entry| entry.next_run)
+            .min()
+    }
+
+    /// Calls `check` with all sites due at the same time, in config order, whenever there are any.
+    /// Returns as soon as a message arrives on `shutdown` or its sender is dropped. Checks in
+    /// progress are always completed first.
+    pub fn run<F>(&mut self, shutdown: &Receiver<()>, mut check: F) where F: FnMut(&[&Site]) {
+        while let Some(next_run) = self.next_run() {
+            let wait = next_run.saturating_duration_since(Instant::now());
+            match shutdown.recv_timeout(wait) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(_) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            let due: Vec<&Site> = self.entries.iter()
+                .filter(|entry| entry.next_run <= now)
+                .map(|entry| entry.site)
+                .collect();
+            check(&due);
+            self.entries.iter_mut()
+                .filter(|entry| entry.next_run <= now)
+                .for_each(|entry| entry.next_run += entry.interval);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn test_config(jitter_seconds: u64) -> SiteConfig {
+        serde_json::from_str(&format!(r#"{{
+            "jitterSeconds": {},
+            "sites": [
+                {{ "targetFqn": "www.tschirky.ch", "service": "flup" }},
+                {{ "targetFqn": "gitea.tschirky.ch", "service": "other", "intervalHours": 2 }}
+            ]
+        }}"#, jitter_seconds)).unwrap()
+    }
+
+    #[test]
+    fn test_per_site_interval() {
+        let config = test_config(0);
+        let scheduler = Scheduler::with_rng(&config, 24, fastrand::Rng::with_seed(1));
+        assert_eq!(scheduler.entries[0].interval, Duration::from_secs(24 * 3600));
+        assert_eq!(scheduler.entries[1].interval, Duration::from_secs(2 * 3600));
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let config = test_config(600);
+        let before = Instant::now();
+        let scheduler = Scheduler::with_rng(&config, 24, fastrand::Rng::with_seed(7));
+        scheduler.entries.iter().for_each(|entry| {
+            assert!(entry.next_run >= before);
+            assert!(entry.next_run <= Instant::now() + Duration::from_secs(600));
+        });
+    }
+
+    #[test]
+    fn test_shutdown_after_due_checks() {
+        let config = test_config(0);
+        let mut scheduler = Scheduler::with_rng(&config, 24, fastrand::Rng::with_seed(1));
+        let (tx, rx) = mpsc::channel();
+        let mut checked = vec![];
+        scheduler.run(&rx, |sites| {
+            checked.extend(sites.iter().map(|site| site.target_fqn.clone()));
+            if checked.len() >= 2 {
+                tx.send(()).unwrap();
+            }
+        });
+        checked.sort();
+        assert_eq!(checked, ["gitea.tschirky.ch", "www.tschirky.ch"]);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::nagios::State;
+use serde::{Deserialize, Serialize};
+
+fn default_level_state() -> State { State::Warning }
+
+/// Named threshold of remaining days, e.g. `info` at 45 days, `warning` at 21 and `critical` at 7
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeverityLevel {
+    pub name: String,
+
+    /// The level is reached when fewer days than this remain
+    pub days: i64,
+
+    /// State reported to Nagios/Icinga and used for the exit code once the level is reached
+    #[serde(default = "default_level_state")]
+    pub state: State,
+}
+
+impl SeverityLevel {
+    pub fn new(name: &str, days: i64, state: State) -> SeverityLevel {
+        SeverityLevel {
+            name: name.to_owned(),
+            days,
+            state,
+        }
+    }
+}
+
+/// Levels used when neither the site nor the config defines any
+pub fn default_levels() -> Vec<SeverityLevel> {
+    warning_critical_levels(15, 7)
+}
+
+pub fn warning_critical_levels(warning_days: i64, critical_days: i64) -> Vec<SeverityLevel> {
+    vec![
+        SeverityLevel::new("warning", warning_days, State::Warning),
+        SeverityLevel::new("critical", critical_days, State::Critical),
+    ]
+}
+
+/// The most severe level reached, which is the one with the lowest threshold still above `remaining_days`
+pub fn reached_level(levels: &[SeverityLevel], remaining_days: i64) -> Option<&SeverityLevel> {
+    levels.iter()
+        .filter(|level| remaining_days < level.days)
+        .min_by_key(|level| level.days)
+}
+
+/// Highest threshold of all levels reporting `state`, as used in the Nagios performance data
+pub fn threshold(levels: &[SeverityLevel], state: State) -> Option<i64> {
+    levels.iter()
+        .filter(|level| level.state == state)
+        .map(|level| level.days)
+        .max()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runbook_levels() -> Vec<SeverityLevel> {
+        serde_json::from_str(r#"[
+            { "name": "critical", "days": 7, "state": "critical" },
+            { "name": "info", "days": 45, "state": "ok" },
+            { "name": "warn", "days": 21 }
+        ]"#).unwrap()
+    }
+
+    #[test]
+    fn test_reached_level() {
+        let levels = runbook_levels();
+        assert_eq!(reached_level(&levels, 60), None);
+        assert_eq!(reached_level(&levels, 45), None);
+        assert_eq!(reached_level(&levels, 44).unwrap().name, "info");
+        assert_eq!(reached_level(&levels, 20).unwrap().name, "warn");
+        assert_eq!(reached_level(&levels, 20).unwrap().state, State::Warning);
+        assert_eq!(reached_level(&levels, 6).unwrap().name, "critical");
+        assert_eq!(reached_level(&levels, -10).unwrap().state, State::Critical);
+    }
+
+    #[test]
+    fn test_threshold() {
+        let levels = runbook_levels();
+        assert_eq!(threshold(&levels, State::Warning), Some(21));
+        assert_eq!(threshold(&levels, State::Critical), Some(7));
+        assert_eq!(threshold(&levels, State::Unknown), None);
+    }
+}
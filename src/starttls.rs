@@ -0,0 +1,567 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::io::{Read, Write};
+
+/// Longest line or XML stanza accepted from the server during the negotiation
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// OID of the LDAP StartTLS extended operation (RFC 4511)
+const LDAP_STARTTLS_OID: &[u8] = b"1.3.6.1.4.1.1466.20037";
+
+/// Name announced to SMTP servers in EHLO
+const CLIENT_NAME: &str = "cert-monitor";
+
+/// SSLRequest code of the PostgreSQL frontend/backend protocol
+const POSTGRES_SSL_REQUEST_CODE: u32 = 80877103;
+
+/// Capability flags of the MySQL client/server protocol
+const MYSQL_CLIENT_LONG_PASSWORD: u32 = 0x0001;
+const MYSQL_CLIENT_PROTOCOL_41: u32 = 0x0200;
+const MYSQL_CLIENT_SSL: u32 = 0x0800;
+const MYSQL_CLIENT_SECURE_CONNECTION: u32 = 0x8000;
+
+/// utf8mb4_general_ci
+const MYSQL_CHARSET: u8 = 45;
+
+/// Plaintext protocol which upgrades the connection to TLS before the handshake
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartTls {
+    Smtp,
+    Imap,
+    Pop3,
+    Ftp,
+    Ldap,
+    /// Client to server XMPP
+    Xmpp,
+    /// PostgreSQL SSLRequest
+    Postgres,
+    /// MySQL and MariaDB SSL request packet
+    Mysql,
+}
+
+/// Failure of the plaintext negotiation
+#[derive(Debug)]
+pub enum NegotiationError {
+    /// The server does not offer STARTTLS or refused the command
+    NotOffered(String),
+    /// The server answered something unexpected
+    Protocol(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for NegotiationError {
+    fn from(e: io::Error) -> NegotiationError {
+        NegotiationError::Io(e)
+    }
+}
+
+impl StartTls {
+    pub fn label(&self) -> &'static str {
+        match self {
+            StartTls::Smtp => "SMTP",
+            StartTls::Imap => "IMAP",
+            StartTls::Pop3 => "POP3",
+            StartTls::Ftp => "FTP",
+            StartTls::Ldap => "LDAP",
+            StartTls::Xmpp => "XMPP",
+            StartTls::Postgres => "PostgreSQL",
+            StartTls::Mysql => "MySQL",
+        }
+    }
+
+    /// Protocol implied by the `service` of a site, for the services which never speak implicit TLS
+    pub fn for_service(service: &str) -> Option<StartTls> {
+        match service.to_ascii_lowercase().as_str() {
+            "postgres" | "postgresql" => Some(StartTls::Postgres),
+            "mysql" | "mariadb" => Some(StartTls::Mysql),
+            _ => None,
+        }
+    }
+
+    /// Runs the plaintext part of the protocol on `sock` until the server is ready for the TLS handshake
+    pub fn negotiate<S: Read + Write>(&self, sock: &mut S, host: &str) -> Result<(), NegotiationError> {
+        match self {
+            StartTls::Smtp => negotiate_smtp(sock),
+            StartTls::Imap => negotiate_imap(sock),
+            StartTls::Pop3 => negotiate_pop3(sock),
+            StartTls::Ftp => negotiate_ftp(sock),
+            StartTls::Ldap => negotiate_ldap(sock),
+            StartTls::Xmpp => negotiate_xmpp(sock, host),
+            StartTls::Postgres => negotiate_postgres(sock),
+            StartTls::Mysql => negotiate_mysql(sock),
+        }
+    }
+}
+
+/// Reads a single line without consuming anything after it, as the TLS handshake follows directly
+fn read_line<S: Read>(sock: &mut S) -> Result<String, NegotiationError> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    while line.len() < MAX_RESPONSE_BYTES {
+        if sock.read(&mut byte)? == 0 {
+            return Err(NegotiationError::Protocol("connection closed by the server".to_owned()));
+        }
+        if byte[0] == b'\n' {
+            let line = String::from_utf8_lossy(&line);
+            return Ok(line.trim_end_matches('\r').to_owned());
+        }
+        line.push(byte[0]);
+    }
+    Err(NegotiationError::Protocol("response line too long".to_owned()))
+}
+
+/// Reads a reply of the form `NNN-text` .. `NNN text` as used by SMTP and FTP
+fn read_reply<S: Read>(sock: &mut S) -> Result<(u16, Vec<String>), NegotiationError> {
+    let mut lines = Vec::new();
+    loop {
+        let line = read_line(sock)?;
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| NegotiationError::Protocol(format!("unexpected reply '{}'", line)))?;
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line.get(4..).unwrap_or_default().to_owned());
+        if last {
+            return Ok((code, lines));
+        }
+    }
+}
+
+fn send<S: Write>(sock: &mut S, command: &str) -> Result<(), NegotiationError> {
+    sock.write_all(command.as_bytes())?;
+    sock.flush()?;
+    Ok(())
+}
+
+fn expect_code(expected: u16, code: u16, lines: &[String], step: &str) -> Result<(), NegotiationError> {
+    if code == expected {
+        Ok(())
+    } else {
+        Err(NegotiationError::Protocol(format!("{} answered with {} {}", step, code, lines.join(" "))))
+    }
+}
+
+fn negotiate_smtp<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    let (code, lines) = read_reply(sock)?;
+    expect_code(220, code, &lines, "greeting")?;
+    send(sock, &format!("EHLO {}\r\n", CLIENT_NAME))?;
+    let (code, lines) = read_reply(sock)?;
+    expect_code(250, code, &lines, "EHLO")?;
+    if !lines.iter().any(|line| line.eq_ignore_ascii_case("STARTTLS")) {
+        return Err(NegotiationError::NotOffered("SMTP server does not offer STARTTLS".to_owned()));
+    }
+    send(sock, "STARTTLS\r\n")?;
+    match read_reply(sock)? {
+        (220, _) => Ok(()),
+        (code, lines) => Err(NegotiationError::NotOffered(format!("SMTP server refused STARTTLS with {} {}", code, lines.join(" ")))),
+    }
+}
+
+/// Reads the responses to an IMAP command up to the tagged one and returns the untagged ones and the status
+fn read_imap_response<S: Read>(sock: &mut S, tag: &str) -> Result<(Vec<String>, String), NegotiationError> {
+    let mut untagged = Vec::new();
+    loop {
+        let line = read_line(sock)?;
+        match line.strip_prefix(tag).and_then(|rest| rest.strip_prefix(' ')) {
+            Some(status) => return Ok((untagged, status.to_owned())),
+            None => untagged.push(line),
+        }
+    }
+}
+
+fn negotiate_imap<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    let greeting = read_line(sock)?;
+    if !greeting.starts_with("* OK") {
+        return Err(NegotiationError::Protocol(format!("unexpected IMAP greeting '{}'", greeting)));
+    }
+    send(sock, "a001 CAPABILITY\r\n")?;
+    let (untagged, _) = read_imap_response(sock, "a001")?;
+    let offered = untagged.iter()
+        .filter(|line| line.starts_with("* CAPABILITY"))
+        .any(|line| line.split_whitespace().any(|capability| capability.eq_ignore_ascii_case("STARTTLS")));
+    if !offered {
+        return Err(NegotiationError::NotOffered("IMAP server does not offer STARTTLS".to_owned()));
+    }
+    send(sock, "a002 STARTTLS\r\n")?;
+    let (_, status) = read_imap_response(sock, "a002")?;
+    if status.starts_with("OK") {
+        Ok(())
+    } else {
+        Err(NegotiationError::NotOffered(format!("IMAP server refused STARTTLS with {}", status)))
+    }
+}
+
+fn negotiate_pop3<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    let greeting = read_line(sock)?;
+    if !greeting.starts_with("+OK") {
+        return Err(NegotiationError::Protocol(format!("unexpected POP3 greeting '{}'", greeting)));
+    }
+    send(sock, "CAPA\r\n")?;
+    let mut offered = false;
+    if read_line(sock)?.starts_with("+OK") {
+        loop {
+            let line = read_line(sock)?;
+            if line == "." {
+                break;
+            }
+            offered |= line.eq_ignore_ascii_case("STLS");
+        }
+    }
+    if !offered {
+        return Err(NegotiationError::NotOffered("POP3 server does not offer STLS".to_owned()));
+    }
+    send(sock, "STLS\r\n")?;
+    let reply = read_line(sock)?;
+    if reply.starts_with("+OK") {
+        Ok(())
+    } else {
+        Err(NegotiationError::NotOffered(format!("POP3 server refused STLS with '{}'", reply)))
+    }
+}
+
+fn negotiate_ftp<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    let (code, lines) = read_reply(sock)?;
+    expect_code(220, code, &lines, "greeting")?;
+    send(sock, "AUTH TLS\r\n")?;
+    match read_reply(sock)? {
+        (234, _) => Ok(()),
+        (code, lines) if code >= 500 => Err(NegotiationError::NotOffered(format!("FTP server refused AUTH TLS with {} {}", code, lines.join(" ")))),
+        (code, lines) => Err(NegotiationError::Protocol(format!("AUTH TLS answered with {} {}", code, lines.join(" ")))),
+    }
+}
+
+/// Reads a BER length, short or long form
+fn read_ber_length<S: Read>(sock: &mut S) -> Result<usize, NegotiationError> {
+    let mut byte = [0u8; 1];
+    sock.read_exact(&mut byte)?;
+    if byte[0] & 0x80 == 0 {
+        return Ok(byte[0] as usize);
+    }
+    let count = (byte[0] & 0x7f) as usize;
+    if count == 0 || count > 4 {
+        return Err(NegotiationError::Protocol("invalid LDAP message length".to_owned()));
+    }
+    let mut length = 0usize;
+    for _ in 0..count {
+        sock.read_exact(&mut byte)?;
+        length = (length << 8) | byte[0] as usize;
+    }
+    Ok(length)
+}
+
+/// Splits the next BER element off `data` and returns its tag and content
+fn next_ber_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+    let (length, rest) = if length & 0x80 == 0 {
+        (length as usize, rest)
+    } else {
+        let count = (length & 0x7f) as usize;
+        let (bytes, rest) = rest.split_at_checked(count)?;
+        (bytes.iter().fold(0usize, |length, &b| (length << 8) | b as usize), rest)
+    };
+    let (content, rest) = rest.split_at_checked(length)?;
+    Some((tag, content, rest))
+}
+
+fn negotiate_ldap<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    // LDAPMessage { messageID 1, ExtendedRequest { requestName [0] StartTLS OID } }
+    let oid_len = LDAP_STARTTLS_OID.len() as u8;
+    let mut request = vec![0x30, oid_len + 7, 0x02, 0x01, 0x01, 0x77, oid_len + 2, 0x80, oid_len];
+    request.extend_from_slice(LDAP_STARTTLS_OID);
+    sock.write_all(&request)?;
+    sock.flush()?;
+
+    let mut tag = [0u8; 1];
+    sock.read_exact(&mut tag)?;
+    if tag[0] != 0x30 {
+        return Err(NegotiationError::Protocol(format!("unexpected LDAP message tag {:#04x}", tag[0])));
+    }
+    let length = read_ber_length(sock)?;
+    if length > MAX_RESPONSE_BYTES {
+        return Err(NegotiationError::Protocol("LDAP response too long".to_owned()));
+    }
+    let mut message = vec![0u8; length];
+    sock.read_exact(&mut message)?;
+
+    // messageID, then ExtendedResponse [APPLICATION 24] starting with the resultCode
+    let result_code = next_ber_element(&message)
+        .and_then(|(_, _, rest)| next_ber_element(rest))
+        .filter(|(tag, _, _)| *tag == 0x78)
+        .and_then(|(_, response, _)| next_ber_element(response))
+        .filter(|(tag, _, _)| *tag == 0x0a)
+        .and_then(|(_, code, _)| code.last().copied())
+        .ok_or_else(|| NegotiationError::Protocol("malformed LDAP extended response".to_owned()))?;
+    match result_code {
+        0 => Ok(()),
+        code => Err(NegotiationError::NotOffered(format!("LDAP server refused StartTLS with result code {}", code))),
+    }
+}
+
+/// Reads from the XMPP stream until one of `markers` was received and returns everything read
+fn read_xml_until<S: Read>(sock: &mut S, markers: &[&str]) -> Result<String, NegotiationError> {
+    let mut data = Vec::new();
+    let mut byte = [0u8; 1];
+    while data.len() < MAX_RESPONSE_BYTES {
+        if sock.read(&mut byte)? == 0 {
+            return Err(NegotiationError::Protocol("stream closed by the server".to_owned()));
+        }
+        data.push(byte[0]);
+        if byte[0] == b'>' {
+            let text = String::from_utf8_lossy(&data);
+            if markers.iter().any(|marker| text.contains(marker)) {
+                return Ok(text.into_owned());
+            }
+        }
+    }
+    Err(NegotiationError::Protocol("XMPP stream features too long".to_owned()))
+}
+
+fn negotiate_xmpp<S: Read + Write>(sock: &mut S, host: &str) -> Result<(), NegotiationError> {
+    send(sock, &format!("<?xml version='1.0'?><stream:stream xmlns='jabber:client' \
+        xmlns:stream='http://etherx.jabber.org/streams' to='{}' version='1.0'>", host))?;
+    let features = read_xml_until(sock, &["</stream:features>", "<stream:features/>", "</stream:stream>"])?;
+    if !features.contains("<starttls") {
+        return Err(NegotiationError::NotOffered("XMPP server does not offer STARTTLS".to_owned()));
+    }
+    send(sock, "<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>")?;
+    let answer = read_xml_until(sock, &["<proceed", "<failure", "</stream:stream>"])?;
+    if answer.contains("<proceed") {
+        Ok(())
+    } else {
+        Err(NegotiationError::NotOffered("XMPP server refused STARTTLS".to_owned()))
+    }
+}
+
+fn negotiate_postgres<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    let mut request = 8u32.to_be_bytes().to_vec();
+    request.extend_from_slice(&POSTGRES_SSL_REQUEST_CODE.to_be_bytes());
+    sock.write_all(&request)?;
+    sock.flush()?;
+
+    let mut answer = [0u8; 1];
+    sock.read_exact(&mut answer)?;
+    match answer[0] {
+        b'S' => Ok(()),
+        b'N' => Err(NegotiationError::NotOffered("PostgreSQL server does not accept SSL connections".to_owned())),
+        other => Err(NegotiationError::Protocol(format!("unexpected answer {:#04x} to the SSLRequest", other))),
+    }
+}
+
+/// Reads a MySQL packet and returns its sequence id and payload
+fn read_mysql_packet<S: Read>(sock: &mut S) -> Result<(u8, Vec<u8>), NegotiationError> {
+    let mut header = [0u8; 4];
+    sock.read_exact(&mut header)?;
+    let length = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    if length > MAX_RESPONSE_BYTES {
+        return Err(NegotiationError::Protocol("MySQL packet too long".to_owned()));
+    }
+    let mut payload = vec![0u8; length];
+    sock.read_exact(&mut payload)?;
+    Ok((header[3], payload))
+}
+
+fn negotiate_mysql<S: Read + Write>(sock: &mut S) -> Result<(), NegotiationError> {
+    let (sequence, greeting) = read_mysql_packet(sock)?;
+    match greeting.first() {
+        Some(10) => {}
+        // ERR packet, e.g. because the host is blocked: error code and message follow
+        Some(0xff) => {
+            let message = String::from_utf8_lossy(greeting.get(3..).unwrap_or_default());
+            return Err(NegotiationError::Protocol(format!("MySQL server refused the connection: {}", message)));
+        }
+        _ => return Err(NegotiationError::Protocol("unsupported MySQL protocol version".to_owned())),
+    }
+    // protocol version, server version, connection id, auth plugin data part 1, filler, capability flags
+    let capabilities = greeting.iter().skip(1).position(|&b| b == 0)
+        .map(|version_end| 1 + version_end + 1 + 4 + 8 + 1)
+        .and_then(|offset| greeting.get(offset..offset + 2))
+        .map(|flags| u16::from_le_bytes([flags[0], flags[1]]) as u32)
+        .ok_or_else(|| NegotiationError::Protocol("malformed MySQL handshake".to_owned()))?;
+    if capabilities & MYSQL_CLIENT_SSL == 0 {
+        return Err(NegotiationError::NotOffered("MySQL server does not support SSL".to_owned()));
+    }
+
+    let flags = MYSQL_CLIENT_LONG_PASSWORD | MYSQL_CLIENT_PROTOCOL_41 | MYSQL_CLIENT_SSL | MYSQL_CLIENT_SECURE_CONNECTION;
+    let mut payload = flags.to_le_bytes().to_vec();
+    payload.extend_from_slice(&(16u32 * 1024 * 1024).to_le_bytes());
+    payload.push(MYSQL_CHARSET);
+    payload.extend_from_slice(&[0u8; 23]);
+    let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+    packet.push(sequence.wrapping_add(1));
+    packet.extend_from_slice(&payload);
+    sock.write_all(&packet)?;
+    sock.flush()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Plays the server side of a conversation: the scripted answers are read by the client, the
+    /// client's commands are collected in `sent`
+    struct FakeServer {
+        answers: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl FakeServer {
+        fn new(answers: &[u8]) -> FakeServer {
+            FakeServer { answers: Cursor::new(answers.to_vec()), sent: Vec::new() }
+        }
+
+        fn sent(&self) -> String {
+            String::from_utf8_lossy(&self.sent).into_owned()
+        }
+    }
+
+    impl Read for FakeServer {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.answers.read(buf)
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn negotiate(protocol: StartTls, answers: &[u8]) -> (Result<(), NegotiationError>, String) {
+        let mut server = FakeServer::new(answers);
+        let result = protocol.negotiate(&mut server, "mail.tschirky.ch");
+        (result, server.sent())
+    }
+
+    #[test]
+    fn test_smtp() {
+        let (result, sent) = negotiate(StartTls::Smtp,
+            b"220-mail.tschirky.ch ESMTP\r\n220 ready\r\n250-mail.tschirky.ch\r\n250-PIPELINING\r\n250-STARTTLS\r\n250 8BITMIME\r\n220 go ahead\r\n");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(sent, "EHLO cert-monitor\r\nSTARTTLS\r\n");
+
+        let (result, sent) = negotiate(StartTls::Smtp, b"220 ready\r\n250-mail.tschirky.ch\r\n250 8BITMIME\r\n");
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+        assert_eq!(sent, "EHLO cert-monitor\r\n");
+
+        let (result, _) = negotiate(StartTls::Smtp, b"554 go away\r\n");
+        assert!(matches!(result, Err(NegotiationError::Protocol(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_imap() {
+        let (result, sent) = negotiate(StartTls::Imap,
+            b"* OK IMAP4rev1 ready\r\n* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED\r\na001 OK done\r\na002 OK begin TLS\r\n");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(sent, "a001 CAPABILITY\r\na002 STARTTLS\r\n");
+
+        let (result, _) = negotiate(StartTls::Imap, b"* OK ready\r\n* CAPABILITY IMAP4rev1\r\na001 OK done\r\n");
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_pop3() {
+        let (result, sent) = negotiate(StartTls::Pop3, b"+OK POP3 ready\r\n+OK\r\nUSER\r\nSTLS\r\n.\r\n+OK begin TLS\r\n");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(sent, "CAPA\r\nSTLS\r\n");
+
+        let (result, _) = negotiate(StartTls::Pop3, b"+OK POP3 ready\r\n-ERR unknown command\r\n");
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_ftp() {
+        let (result, sent) = negotiate(StartTls::Ftp, b"220-Welcome\r\n220 FTP ready\r\n234 AUTH TLS OK\r\n");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(sent, "AUTH TLS\r\n");
+
+        let (result, _) = negotiate(StartTls::Ftp, b"220 FTP ready\r\n502 not implemented\r\n");
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_ldap() {
+        // LDAPMessage { 1, ExtendedResponse { success, "", "" } }
+        let (result, sent) = negotiate(StartTls::Ldap, &[0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(sent.ends_with("1.3.6.1.4.1.1466.20037"));
+        assert_eq!(sent.as_bytes()[..2], [0x30, 0x1d]);
+
+        // protocolError, as sent by servers without StartTLS support
+        let (result, _) = negotiate(StartTls::Ldap, &[0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00]);
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+
+        let (result, _) = negotiate(StartTls::Ldap, b"HTTP/1.1 400 Bad Request\r\n");
+        assert!(matches!(result, Err(NegotiationError::Protocol(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_xmpp() {
+        let (result, sent) = negotiate(StartTls::Xmpp,
+            b"<?xml version='1.0'?><stream:stream xmlns='jabber:client' id='1' version='1.0'>\
+              <stream:features><starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'><required/></starttls></stream:features>\
+              <proceed xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>");
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(sent.contains("to='mail.tschirky.ch'"));
+        assert!(sent.ends_with("<starttls xmlns='urn:ietf:params:xml:ns:xmpp-tls'/>"));
+
+        let (result, _) = negotiate(StartTls::Xmpp,
+            b"<stream:stream version='1.0'><stream:features><mechanisms/></stream:features>");
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_postgres() {
+        let mut server = FakeServer::new(b"S");
+        StartTls::Postgres.negotiate(&mut server, "db.tschirky.ch").unwrap();
+        assert_eq!(server.sent, [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]);
+
+        let (result, _) = negotiate(StartTls::Postgres, b"N");
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+    }
+
+    /// Initial handshake packet of a MySQL server with the given lower capability flags
+    fn mysql_greeting(capabilities: u16) -> Vec<u8> {
+        let mut payload = vec![10];
+        payload.extend_from_slice(b"8.0.36\0");
+        payload.extend_from_slice(&[1, 0, 0, 0]);
+        payload.extend_from_slice(b"abcdefgh\0");
+        payload.extend_from_slice(&capabilities.to_le_bytes());
+        payload.extend_from_slice(&[45, 2, 0, 0xff, 0xff]);
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(0);
+        packet.extend_from_slice(&payload);
+        packet
+    }
+
+    #[test]
+    fn test_mysql() {
+        let mut server = FakeServer::new(&mysql_greeting(0xffff));
+        StartTls::Mysql.negotiate(&mut server, "db.tschirky.ch").unwrap();
+        assert_eq!(server.sent.len(), 36);
+        assert_eq!(server.sent[..4], [32, 0, 0, 1]);
+        let flags = u32::from_le_bytes(server.sent[4..8].try_into().unwrap());
+        assert_ne!(flags & MYSQL_CLIENT_SSL, 0);
+
+        let (result, sent) = negotiate(StartTls::Mysql, &mysql_greeting(!(MYSQL_CLIENT_SSL as u16)));
+        assert!(matches!(result, Err(NegotiationError::NotOffered(_))), "{:?}", result);
+        assert!(sent.is_empty());
+
+        let (result, _) = negotiate(StartTls::Mysql, b"\x16\0\0\0\xff\x6a\x04Host is not allowed");
+        assert!(matches!(result, Err(NegotiationError::Protocol(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_for_service() {
+        assert_eq!(StartTls::for_service("PostgreSQL"), Some(StartTls::Postgres));
+        assert_eq!(StartTls::for_service("mariadb"), Some(StartTls::Mysql));
+        assert_eq!(StartTls::for_service("flup"), None);
+    }
+}
@@ -0,0 +1,512 @@
+use crate::cert_retriever::SimpleCertificate;
+use crate::lint::{is_short_key, is_weak_signature, LintFinding};
+use crate::nagios::State;
+use crate::output::{state_style, CheckResult, ResultWriter, PIN_MISMATCH};
+use crate::profile;
+use crate::profile::PublicKeyInfo;
+use console::{style, Style};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use std::fmt::Display;
+use std::io;
+use std::io::Write;
+use std::net::IpAddr;
+use time::macros::format_description;
+use time::{OffsetDateTime, UtcOffset};
+use x509_parser::asn1_rs::Any;
+use x509_parser::extensions::{DistributionPointName, GeneralName, ParsedExtension, X509Extension};
+use x509_parser::objects::{oid2description, oid_registry};
+use x509_parser::oid_registry::{Oid, OID_CT_LIST_SCT, OID_PKIX_ACCESS_DESCRIPTOR_CA_ISSUERS, OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+                                OID_PKIX_AUTHORITY_INFO_ACCESS};
+use x509_parser::prelude::{FromDer, X509Certificate};
+use x509_parser::public_key::PublicKey;
+
+/// Policy qualifier with the URL of a certification practice statement
+const CPS_QUALIFIER: &str = "1.3.6.1.5.5.7.2.1";
+
+/// Writes a decoded view of every certificate of the chain, as `openssl x509 -text` does, and highlights
+/// problems like short keys, weak signature algorithms and imminent expiry
+pub struct TextWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextWriter<W> {
+    pub fn new(out: W) -> TextWriter<W> {
+        TextWriter { out }
+    }
+}
+
+impl<W: Write> ResultWriter for TextWriter<W> {
+    fn write_result(&mut self, result: &CheckResult) -> io::Result<()> {
+        if let Some(e) = &result.error {
+            writeln!(self.out, "{}", problem().apply_to(format!("{}: <{} : {}>", result.endpoint(), e.kind(), e.message())))?;
+            return writeln!(self.out);
+        }
+        for (index, element) in result.chain.iter().enumerate() {
+            writeln!(self.out, "{}", style(format!("{} - certificate {} of {}", result.endpoint(), index + 1, result.chain.len())).bold())?;
+            write!(self.out, "{}", certificate_text(&element.certificate, element.state))?;
+            write!(self.out, "{}", lint_text(&element.lints))?;
+            writeln!(self.out)?;
+        }
+        if !result.policy_violations.is_empty() {
+            writeln!(self.out, "{}", style(format!("{} - policy violations", result.endpoint())).bold())?;
+            for violation in &result.policy_violations {
+                let line = format!("{} {}: {}", violation.policy, violation.requirement, violation.message);
+                writeln!(self.out, "    {}", state_style(violation.state).apply_to(line))?;
+            }
+            writeln!(self.out)?;
+        }
+        if !result.changes.is_empty() {
+            writeln!(self.out, "{}", style(format!("{} - changes since the last run", result.endpoint())).bold())?;
+            for change in &result.changes {
+                let line = format!("{}: {}", change.event.label(), change.message);
+                writeln!(self.out, "    {}", state_style(change.event.state()).apply_to(line))?;
+            }
+            writeln!(self.out)?;
+        }
+        if result.pinned == Some(false) {
+            writeln!(self.out, "{}", style(format!("{} - pin mismatch", result.endpoint())).bold())?;
+            writeln!(self.out, "    {}", state_style(State::Critical).apply_to(PIN_MISMATCH))?;
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+}
+
+fn problem() -> Style {
+    Style::new().red().bold()
+}
+
+/// Lines of text, each indented by the given number of spaces
+#[derive(Default)]
+struct Text {
+    text: String,
+}
+
+impl Text {
+    fn line(&mut self, indent: usize, line: impl Display) {
+        self.text.push_str(&format!("{:indent$}{}\n", "", line, indent = indent));
+    }
+
+    fn lines(&mut self, indent: usize, lines: impl IntoIterator<Item=String>) {
+        lines.into_iter().for_each(|line| self.line(indent, line));
+    }
+}
+
+/// The decoded certificate, its expiry highlighted in the style of the reached `state`
+pub fn certificate_text(cert: &SimpleCertificate, state: State) -> String {
+    let der = match CertificateDer::from_pem_slice(cert.get_pem().as_bytes()) {
+        Ok(der) => der,
+        Err(e) => return format!("{}\n", problem().apply_to(format!("<unable to decode the certificate: {}>", e))),
+    };
+    let x509 = match X509Certificate::from_der(der.as_ref()) {
+        Ok((_, x509)) => x509,
+        Err(e) => return format!("{}\n", problem().apply_to(format!("<unable to decode the certificate: {}>", e))),
+    };
+    let mut text = Text::default();
+    text.line(0, "Certificate:");
+    text.line(4, "Data:");
+    text.line(8, format!("Version: {} (0x{:x})", x509.version().0 + 1, x509.version().0));
+    // without the leading zero which keeps the DER integer positive
+    let serial = match x509.raw_serial() {
+        [0, serial @ ..] if !serial.is_empty() => serial,
+        serial => serial,
+    };
+    if serial.len() <= 8 {
+        let value = serial.iter().fold(0u64, |value, byte| value << 8 | *byte as u64);
+        text.line(8, format!("Serial Number: {} (0x{:x})", value, value));
+    } else {
+        text.line(8, "Serial Number:");
+        text.lines(12, hex_lines(serial, 20, false));
+    }
+    text.line(8, format!("Signature Algorithm: {}", signature_algorithm(cert.get_signature_algorithm())));
+    text.line(8, format!("Issuer: {}", cert.get_issuer()));
+    text.line(8, "Validity");
+    text.line(12, format!("Not Before: {}{}", openssl_time(cert.get_not_before()), not_yet_valid(cert.get_not_before())));
+    text.line(12, format!("Not After : {} {}", openssl_time(cert.get_expiration_date()), remaining(cert.get_remaining_days(), state)));
+    text.line(8, format!("Subject: {}", cert.get_subject()));
+    text.line(8, "Subject Public Key Info:");
+    public_key_text(&mut text, &x509, cert.get_public_key());
+    if !x509.extensions().is_empty() {
+        text.line(8, "X509v3 extensions:");
+        for extension in x509.extensions() {
+            let critical = if extension.critical { " critical" } else { "" };
+            text.line(12, format!("{}:{}", extension_name(&extension.oid), critical));
+            text.lines(16, extension_lines(extension));
+        }
+    }
+    text.line(4, format!("Signature Algorithm: {}", signature_algorithm(cert.get_signature_algorithm())));
+    text.line(4, "Signature Value:");
+    text.lines(8, hex_lines(&x509.signature_value.data, 18, false));
+    text.text
+}
+
+/// The lint findings of a certificate in the style of the state they raise the result to
+fn lint_text(lints: &[LintFinding]) -> String {
+    let mut text = Text::default();
+    if !lints.is_empty() {
+        text.line(4, "Lint Findings:");
+        for finding in lints {
+            let line = format!("{} ({}): {}", finding.rule, finding.severity.label(), finding.message);
+            text.line(8, state_style(finding.severity.state()).apply_to(line));
+        }
+    }
+    text.text
+}
+
+/// Colon separated hex bytes, `per_line` bytes per line, upper case as for key identifiers or lower case
+fn hex_lines(bytes: &[u8], per_line: usize, upper: bool) -> Vec<String> {
+    let lines: Vec<Vec<String>> = bytes.chunks(per_line)
+        .map(|chunk| chunk.iter().map(|byte| if upper { format!("{:02X}", byte) } else { format!("{:02x}", byte) }).collect())
+        .collect();
+    let count = lines.len();
+    lines.into_iter().enumerate()
+        .map(|(index, line)| line.join(":") + if index + 1 < count { ":" } else { "" })
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    hex_lines(bytes, bytes.len().max(1), true).concat()
+}
+
+fn openssl_time(date: OffsetDateTime) -> String {
+    let format = format_description!("[month repr:short] [day padding:space] [hour]:[minute]:[second] [year] GMT");
+    date.to_offset(UtcOffset::UTC).format(&format).unwrap_or_default()
+}
+
+fn not_yet_valid(not_before: OffsetDateTime) -> String {
+    if not_before > OffsetDateTime::now_utc() {
+        format!(" {}", problem().apply_to("(not yet valid)"))
+    } else {
+        String::new()
+    }
+}
+
+fn remaining(days: i64, state: State) -> String {
+    if days < 0 {
+        problem().apply_to(format!("(expired {} days ago)", -days)).to_string()
+    } else if state != State::Ok {
+        state_style(state).apply_to(format!("({} days left)", days)).to_string()
+    } else {
+        format!("({} days left)", days)
+    }
+}
+
+fn signature_algorithm(algorithm: &str) -> String {
+    if is_weak_signature(algorithm) {
+        format!("{} {}", algorithm, problem().apply_to("(weak)"))
+    } else {
+        algorithm.to_owned()
+    }
+}
+
+fn public_key_text(text: &mut Text, x509: &X509Certificate, key: &PublicKeyInfo) {
+    let spki = x509.public_key();
+    text.line(12, format!("Public Key Algorithm: {}", profile::oid_name(&spki.algorithm.algorithm)));
+    if let Some(size) = key.size {
+        let short = if is_short_key(key) { format!(" {}", problem().apply_to("(too short)")) } else { String::new() };
+        text.line(16, format!("Public-Key: ({} bit){}", size, short));
+    }
+    match spki.parsed() {
+        Ok(PublicKey::RSA(rsa)) => {
+            text.line(16, "Modulus:");
+            text.lines(20, hex_lines(rsa.modulus, 15, false));
+            match rsa.try_exponent() {
+                Ok(exponent) => text.line(16, format!("Exponent: {} (0x{:x})", exponent, exponent)),
+                Err(_) => text.line(16, format!("Exponent: {}", hex(rsa.exponent))),
+            }
+        }
+        Ok(PublicKey::EC(point)) => {
+            text.line(16, "pub:");
+            text.lines(20, hex_lines(point.data(), 15, false));
+            if let Some(curve) = spki.algorithm.parameters.as_ref().and_then(|parameters| parameters.as_oid().ok()) {
+                text.line(16, format!("ASN1 OID: {}", profile::oid_name(&curve)));
+            }
+            if let Some(curve) = &key.curve {
+                text.line(16, format!("NIST CURVE: {}", curve));
+            }
+        }
+        _ => {
+            text.line(16, "pub:");
+            text.lines(20, hex_lines(&spki.subject_public_key.data, 15, false));
+        }
+    }
+}
+
+/// Description of the extension as used by OpenSSL, e.g. `X509v3 Key Usage`, or its OID if unknown
+fn extension_name(oid: &Oid) -> String {
+    match oid {
+        oid if *oid == OID_PKIX_AUTHORITY_INFO_ACCESS => "Authority Information Access".to_owned(),
+        oid if *oid == OID_CT_LIST_SCT => "CT Precertificate SCTs".to_owned(),
+        oid => oid2description(oid, oid_registry()).map_or_else(|_| oid.to_id_string(), |name| name.to_owned()),
+    }
+}
+
+fn general_name(name: &GeneralName) -> String {
+    match name {
+        GeneralName::DNSName(dns) => format!("DNS:{}", dns),
+        GeneralName::RFC822Name(email) => format!("email:{}", email),
+        GeneralName::URI(uri) => format!("URI:{}", uri),
+        GeneralName::DirectoryName(dn) => format!("DirName:{}", dn),
+        GeneralName::RegisteredID(oid) => format!("Registered ID:{}", oid.to_id_string()),
+        GeneralName::IPAddress(bytes) => match <[u8; 4]>::try_from(*bytes) {
+            Ok(octets) => format!("IP Address:{}", IpAddr::from(octets)),
+            Err(_) => match <[u8; 16]>::try_from(*bytes) {
+                Ok(octets) => format!("IP Address:{}", IpAddr::from(octets)),
+                Err(_) => format!("IP Address:<invalid {}>", hex(bytes)),
+            },
+        },
+        other => other.to_string(),
+    }
+}
+
+fn general_names(names: &[GeneralName]) -> String {
+    names.iter().map(general_name).collect::<Vec<_>>().join(", ")
+}
+
+/// Names of the extended key usages as shown by OpenSSL
+fn extended_key_usage_names(cert_eku: &x509_parser::extensions::ExtendedKeyUsage) -> String {
+    let mut names: Vec<String> = [
+        (cert_eku.any, "Any Extended Key Usage"),
+        (cert_eku.server_auth, "TLS Web Server Authentication"),
+        (cert_eku.client_auth, "TLS Web Client Authentication"),
+        (cert_eku.code_signing, "Code Signing"),
+        (cert_eku.email_protection, "E-mail Protection"),
+        (cert_eku.time_stamping, "Time Stamping"),
+        (cert_eku.ocsp_signing, "OCSP Signing"),
+    ].into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name.to_owned())
+        .collect();
+    names.extend(cert_eku.other.iter().map(|oid| oid.to_id_string()));
+    names.join(", ")
+}
+
+/// The text of a policy qualifier, e.g. the URL of the CPS, or its hex encoding
+fn policy_qualifier(qualifier: &[u8]) -> String {
+    Any::from_der(qualifier).ok()
+        .and_then(|(_, any)| std::str::from_utf8(any.data).ok().map(|text| text.to_owned()))
+        .unwrap_or_else(|| hex(qualifier))
+}
+
+/// Decoded value of the extension, the raw value in hex if it is not supported
+fn extension_lines(extension: &X509Extension) -> Vec<String> {
+    match extension.parsed_extension() {
+        ParsedExtension::BasicConstraints(constraints) => {
+            let ca = if constraints.ca { "CA:TRUE" } else { "CA:FALSE" };
+            match constraints.path_len_constraint {
+                Some(path_len) => vec![format!("{}, pathlen:{}", ca, path_len)],
+                None => vec![ca.to_owned()],
+            }
+        }
+        ParsedExtension::KeyUsage(usage) => vec![usage.to_string()],
+        ParsedExtension::ExtendedKeyUsage(eku) => vec![extended_key_usage_names(eku)],
+        ParsedExtension::SubjectKeyIdentifier(key_id) => vec![hex(key_id.0)],
+        ParsedExtension::AuthorityKeyIdentifier(aki) => {
+            let mut lines: Vec<String> = aki.key_identifier.iter().map(|key_id| hex(key_id.0)).collect();
+            if let Some(issuer) = &aki.authority_cert_issuer {
+                lines.push(general_names(issuer));
+            }
+            if let Some(serial) = aki.authority_cert_serial {
+                lines.push(format!("serial:{}", hex(serial)));
+            }
+            lines
+        }
+        ParsedExtension::SubjectAlternativeName(san) => vec![general_names(&san.general_names)],
+        ParsedExtension::IssuerAlternativeName(ian) => vec![general_names(&ian.general_names)],
+        ParsedExtension::AuthorityInfoAccess(aia) => aia.accessdescs.iter()
+            .map(|description| {
+                let method = match &description.access_method {
+                    method if *method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP => "OCSP".to_owned(),
+                    method if *method == OID_PKIX_ACCESS_DESCRIPTOR_CA_ISSUERS => "CA Issuers".to_owned(),
+                    method => profile::oid_name(method),
+                };
+                format!("{} - {}", method, general_name(&description.access_location))
+            })
+            .collect(),
+        ParsedExtension::CRLDistributionPoints(points) => points.iter()
+            .flat_map(|point| match &point.distribution_point {
+                Some(DistributionPointName::FullName(names)) => {
+                    let mut lines = vec!["Full Name:".to_owned()];
+                    lines.extend(names.iter().map(|name| format!("  {}", general_name(name))));
+                    lines
+                }
+                Some(DistributionPointName::NameRelativeToCRLIssuer(name)) => {
+                    vec![format!("Relative Name: {:?}", name)]
+                }
+                None => Vec::new(),
+            })
+            .collect(),
+        ParsedExtension::CertificatePolicies(policies) => policies.iter()
+            .flat_map(|policy| {
+                let mut lines = vec![format!("Policy: {}", policy.policy_id.to_id_string())];
+                for qualifier in policy.policy_qualifiers.iter().flatten() {
+                    let id = qualifier.policy_qualifier_id.to_id_string();
+                    let label = if id == CPS_QUALIFIER { "CPS".to_owned() } else { id };
+                    lines.push(format!("  {}: {}", label, policy_qualifier(qualifier.qualifier)));
+                }
+                lines
+            })
+            .collect(),
+        ParsedExtension::PolicyMappings(mappings) => mappings.mappings.iter()
+            .map(|mapping| format!("{} -> {}", mapping.issuer_domain_policy.to_id_string(), mapping.subject_domain_policy.to_id_string()))
+            .collect(),
+        ParsedExtension::PolicyConstraints(constraints) => [
+            constraints.require_explicit_policy.map(|skip| format!("Require Explicit Policy:{}", skip)),
+            constraints.inhibit_policy_mapping.map(|skip| format!("Inhibit Policy Mapping:{}", skip)),
+        ].into_iter().flatten().collect(),
+        ParsedExtension::InhibitAnyPolicy(inhibit) => vec![inhibit.skip_certs.to_string()],
+        ParsedExtension::NameConstraints(constraints) => {
+            let mut lines = Vec::new();
+            for (label, subtrees) in [("Permitted:", &constraints.permitted_subtrees), ("Excluded:", &constraints.excluded_subtrees)] {
+                if let Some(subtrees) = subtrees {
+                    lines.push(label.to_owned());
+                    lines.extend(subtrees.iter().map(|subtree| format!("  {}", general_name(&subtree.base))));
+                }
+            }
+            lines
+        }
+        ParsedExtension::SCT(timestamps) => timestamps.iter()
+            .flat_map(|sct| {
+                let mut lines = vec![
+                    "Signed Certificate Timestamp:".to_owned(),
+                    format!("    Version   : v{} (0x{:x})", sct.version.0 + 1, sct.version.0),
+                ];
+                let mut labelled = |label: &str, values: Vec<String>| {
+                    let mut values = values.into_iter();
+                    lines.push(format!("    {:<10}: {}", label, values.next().unwrap_or_default()));
+                    lines.extend(values.map(|value| format!("{:16}{}", "", value)));
+                };
+                labelled("Log ID", hex_lines(sct.id.key_id, 16, true));
+                let format = format_description!("[month repr:short] [day padding:space] [hour]:[minute]:[second].[subsecond digits:3] [year] GMT");
+                let timestamp = OffsetDateTime::from_unix_timestamp_nanos(sct.timestamp as i128 * 1_000_000).ok()
+                    .and_then(|timestamp| timestamp.format(&format).ok())
+                    .unwrap_or_else(|| sct.timestamp.to_string());
+                labelled("Timestamp", vec![timestamp]);
+                let extensions = if sct.extensions.0.is_empty() { vec!["none".to_owned()] } else { hex_lines(sct.extensions.0, 16, true) };
+                labelled("Extensions", extensions);
+                let mut signature = vec![sct_signature_algorithm(sct.signature.hash_alg_id, sct.signature.sign_alg_id)];
+                signature.extend(hex_lines(sct.signature.data, 16, true));
+                labelled("Signature", signature);
+                lines
+            })
+            .collect(),
+        ParsedExtension::NSCertType(cert_type) => vec![cert_type.to_string()],
+        ParsedExtension::NsCertComment(comment) => vec![comment.to_string()],
+        ParsedExtension::ParseError { error } => {
+            let mut lines = vec![problem().apply_to(format!("<unable to decode: {}>", error)).to_string()];
+            lines.extend(hex_lines(extension.value, 18, false));
+            lines
+        }
+        _ => hex_lines(extension.value, 18, false),
+    }
+}
+
+/// Name of the signature algorithm of a signed certificate timestamp as defined by TLS 1.2
+fn sct_signature_algorithm(hash: u8, signature: u8) -> String {
+    let hash = match hash {
+        1 => "MD5",
+        2 => "SHA1",
+        3 => "SHA224",
+        4 => "SHA256",
+        5 => "SHA384",
+        6 => "SHA512",
+        _ => "unknown",
+    };
+    match signature {
+        1 => format!("{}WithRSAEncryption", hash.to_ascii_lowercase()),
+        3 => format!("ecdsa-with-{}", hash),
+        _ => format!("unknown ({})", hash),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cert_retriever::{CertError, EndpointCertificate};
+    use crate::client_auth::ClientAuth;
+    use crate::config::Site;
+    use crate::validation::ChainOrder;
+
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+    const PROFILE_CERT: &[u8] = include_bytes!("../testdata/pki/profile.crt");
+    const INTERMEDIATE_CERT: &[u8] = include_bytes!("../testdata/pki/intermediate.crt");
+    const WEAK_CERT: &[u8] = include_bytes!("../testdata/pki/lint/weak.crt");
+
+    fn render(result: &CheckResult) -> String {
+        let mut writer = TextWriter::new(Vec::new());
+        writer.write_result(result).unwrap();
+        console::strip_ansi_codes(std::str::from_utf8(&writer.out).unwrap()).into_owned()
+    }
+
+    fn result(chain: &[CertificateDer]) -> CheckResult {
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "localhost", "service": "other" }"#).unwrap();
+        let chain: Vec<SimpleCertificate> = chain.iter().map(|der| SimpleCertificate::from_certificate_der(der).unwrap()).collect();
+        CheckResult::new(&site, Ok(EndpointCertificate {
+            certificate: chain[0].clone(),
+            chain,
+            chain_order: ChainOrder::Ordered,
+            validation: None,
+            client_auth: ClientAuth::default(),
+        }))
+    }
+
+    #[test]
+    fn test_certificate_text() {
+        let text = render(&result(&[CertificateDer::from_pem_slice(PROFILE_CERT).unwrap(), CertificateDer::from_pem_slice(INTERMEDIATE_CERT).unwrap()]));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "localhost:443 - certificate 1 of 2");
+        for expected in [
+            "        Version: 3 (0x2)",
+            "            51:f5:16:af:5f:63:56:cf:ec:ec:ea:a0:14:50:58:8c:81:8a:34:f6",
+            "        Signature Algorithm: ecdsa-with-SHA384",
+            "        Issuer: O=Cert Monitor Test, CN=Cert Monitor Test Intermediate CA",
+            "            Not Before: Oct 18 08:28:02 2026 GMT",
+            "        Subject: C=CH, O=Cert Monitor Test, CN=profile.test.cert-monitor.local",
+            "            Public Key Algorithm: id-ecPublicKey",
+            "                Public-Key: (384 bit)",
+            "                ASN1 OID: secp384r1",
+            "                NIST CURVE: P-384",
+            "            X509v3 Basic Constraints: critical",
+            "                CA:FALSE",
+            "                Digital Signature, Key Agreement",
+            "                TLS Web Server Authentication, TLS Web Client Authentication",
+            "                DNS:profile.test.cert-monitor.local",
+            "                4E:EB:92:43:26:16:58:78:D2:48:62:3A:A5:BE:9A:8C:23:99:72:55",
+            "                OCSP - URI:http://ocsp.test.cert-monitor.local",
+            "                CA Issuers - URI:http://ca.test.cert-monitor.local/intermediate.crt",
+            "                  URI:http://ca.test.cert-monitor.local/intermediate.crl",
+            "                Policy: 1.3.6.1.4.1.99999.1",
+            "    Signature Value:",
+            "localhost:443 - certificate 2 of 2",
+            "                CA:TRUE, pathlen:0",
+        ] {
+            assert!(lines.contains(&expected), "missing {:?} in\n{}", expected, text);
+        }
+        assert!(lines.iter().any(|line| line.starts_with("            Not After : Aug 16 08:28:02 2122 GMT (") && line.ends_with(" days left)")));
+        assert!(!text.contains("(weak)") && !text.contains("(too short)"));
+    }
+
+    #[test]
+    fn test_rsa_certificate_text() {
+        let text = render(&result(&[CertificateDer::from(GITEA_CERT)]));
+        assert!(text.contains("                Public-Key: (2048 bit)\n                Modulus:\n                    00:"));
+        assert!(text.contains("                Exponent: 65537 (0x10001)\n"));
+        assert!(text.contains(" GMT (expired "));
+        assert!(text.contains("                Signed Certificate Timestamp:\n                    Version   : v1 (0x0)\n"));
+        assert!(text.contains("                    Signature : ecdsa-with-SHA256\n"));
+    }
+
+    #[test]
+    fn test_problems() {
+        let text = console::strip_ansi_codes(&render(&result(&[CertificateDer::from_pem_slice(WEAK_CERT).unwrap()]))).into_owned();
+        assert!(text.contains("        Signature Algorithm: ecdsa-with-SHA1 (weak)\n"));
+        assert!(text.contains("                Public-Key: (1024 bit) (too short)\n"));
+        assert!(text.contains("    Lint Findings:\n        weak-key (error): RSA key of 1024 bits\n        weak-signature (error): Signed with ecdsa-with-SHA1\n"));
+
+        let site: Site = serde_json::from_str(r#"{ "targetFqn": "localhost", "service": "other" }"#).unwrap();
+        let failed = CheckResult::new(&site, Err(CertError::TargetNotReachable("Target localhost:443 is unreachable".to_owned())));
+        assert_eq!(render(&failed), "localhost:443: <TargetNotReachable : Target localhost:443 is unreachable>\n\n");
+    }
+}
@@ -0,0 +1,180 @@
+use crate::cert_retriever::CertRetriever;
+use crate::client_auth::load_client_certificate;
+use crate::config::{Site, SiteConfig, TrustStoreConfig};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::RootCertStore;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Locations of the CA bundle of the common Linux distributions and BSDs
+const SYSTEM_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+fn read_pem_file(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Unable to read certificates from {}: {}", path.display(), e))
+}
+
+fn read_pem_directory(path: &Path) -> Result<Vec<CertificateDer<'static>>, String> {
+    let entries = std::fs::read_dir(path)
+        .map_err(|e| format!("Unable to read directory {}: {}", path.display(), e))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pem" || ext == "crt" || ext == "cer"))
+        .collect();
+    files.sort();
+
+    let mut certs = Vec::new();
+    for file in files {
+        certs.extend(read_pem_file(&file)?);
+    }
+    Ok(certs)
+}
+
+fn system_bundle() -> Result<PathBuf, String> {
+    SYSTEM_BUNDLES.iter()
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!("No system CA bundle found, tried {}", SYSTEM_BUNDLES.join(", ")))
+}
+
+/// Loads the trust anchors described by `config`
+pub fn load_trust_store(config: &TrustStoreConfig) -> Result<RootCertStore, String> {
+    let certs = match config {
+        TrustStoreConfig::Builtin => {
+            return Ok(RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.into() });
+        }
+        TrustStoreConfig::File { path } => read_pem_file(path)?,
+        TrustStoreConfig::Directory { path } => read_pem_directory(path)?,
+        TrustStoreConfig::System { path } => {
+            let path = match path {
+                Some(path) => path.clone(),
+                None => system_bundle()?,
+            };
+            read_pem_file(&path)?
+        }
+    };
+
+    let mut roots = RootCertStore::empty();
+    let (added, _ignored) = roots.add_parsable_certificates(certs);
+    if added == 0 {
+        return Err(format!("Trust store {:?} contains no usable CA certificate", config));
+    }
+    Ok(roots)
+}
+
+/// Names of the trust store and the client certificate used by a site
+type RetrieverKey = (Option<String>, Option<String>);
+
+/// One [CertRetriever] per combination of trust store and client certificate referenced in the config
+pub struct Retrievers {
+    default: CertRetriever,
+    by_key: HashMap<RetrieverKey, CertRetriever>,
+}
+
+impl Retrievers {
+    /// Loads the trust stores and client certificates used by the sites and fails if a site references
+    /// an unknown one
+    pub fn new(site_config: &SiteConfig) -> Result<Retrievers, String> {
+        let mut by_key = HashMap::new();
+        for site in site_config.site_iter() {
+            let key = Retrievers::key(site);
+            if key == (None, None) || by_key.contains_key(&key) {
+                continue;
+            }
+            let roots = match &site.trust_store {
+                Some(name) => {
+                    let store_config = site_config.trust_stores.get(name)
+                        .ok_or_else(|| format!("Site {} references the unknown trust store {}", site.target_fqn, name))?;
+                    load_trust_store(store_config)
+                        .map_err(|e| format!("Unable to load the trust store {}: {}", name, e))?
+                }
+                None => load_trust_store(&TrustStoreConfig::Builtin)?,
+            };
+            let client_key = match &site.client_certificate {
+                Some(name) => {
+                    let client_config = site_config.client_certificates.get(name)
+                        .ok_or_else(|| format!("Site {} references the unknown client certificate {}", site.target_fqn, name))?;
+                    Some(load_client_certificate(client_config)
+                        .map_err(|e| format!("Unable to load the client certificate {}: {}", name, e))?)
+                }
+                None => None,
+            };
+            by_key.insert(key, CertRetriever::with_client_certificate(roots, client_key));
+        }
+        Ok(Retrievers {
+            default: CertRetriever::new(),
+            by_key,
+        })
+    }
+
+    fn key(site: &Site) -> RetrieverKey {
+        (site.trust_store.clone(), site.client_certificate.clone())
+    }
+
+    pub fn for_site(&self, site: &Site) -> &CertRetriever {
+        self.by_key.get(&Retrievers::key(site)).unwrap_or(&self.default)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_file() {
+        let roots = load_trust_store(&TrustStoreConfig::File { path: PathBuf::from("testdata/pki/root.crt") }).unwrap();
+        assert_eq!(roots.len(), 1);
+    }
+
+    #[test]
+    fn test_load_directory() {
+        let roots = load_trust_store(&TrustStoreConfig::Directory { path: PathBuf::from("testdata/pki") }).unwrap();
+        assert_eq!(roots.len(), 6);
+    }
+
+    #[test]
+    fn test_load_builtin() {
+        let roots = load_trust_store(&TrustStoreConfig::Builtin).unwrap();
+        assert!(roots.len() > 100);
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(load_trust_store(&TrustStoreConfig::File { path: PathBuf::from("testdata/pki/missing.crt") }).is_err());
+        // a private key only, no certificate
+        assert!(load_trust_store(&TrustStoreConfig::File { path: PathBuf::from("testdata/pki/root.key") }).is_err());
+        assert!(load_trust_store(&TrustStoreConfig::Directory { path: PathBuf::from("testdata/missing") }).is_err());
+    }
+
+    #[test]
+    fn test_unknown_trust_store() {
+        let config: SiteConfig = serde_json::from_str(r#"{
+            "trustStores": { "corporate": { "type": "file", "path": "testdata/pki/root.crt" } },
+            "sites": [ { "targetFqn": "localhost", "service": "other", "trustStore": "corprate" } ]
+        }"#).unwrap();
+        let e = Retrievers::new(&config).err().unwrap();
+        assert_eq!(e, "Site localhost references the unknown trust store corprate");
+    }
+
+    #[test]
+    fn test_unknown_client_certificate() {
+        let config: SiteConfig = serde_json::from_str(r#"{
+            "clientCertificates": { "monitor": { "type": "pem", "certPath": "testdata/pki/client/client.crt", "keyPath": "testdata/pki/client/client.key" } },
+            "sites": [
+                { "targetFqn": "localhost", "service": "other", "clientCertificate": "monitor" },
+                { "targetFqn": "localhost", "service": "other", "clientCertificate": "monitr" }
+            ]
+        }"#).unwrap();
+        let e = Retrievers::new(&config).err().unwrap();
+        assert_eq!(e, "Site localhost references the unknown client certificate monitr");
+    }
+}
@@ -0,0 +1,263 @@
+use rustls::client::danger::ServerCertVerifier;
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, Error};
+use serde::Serialize;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ValidationStatus {
+    Valid,
+    /// The chain does not lead to one of the trust anchors
+    UntrustedIssuer,
+    /// The end entity certificate signed itself
+    SelfSigned,
+    /// The server sent only the end entity certificate, which was not issued by a trust anchor
+    MissingIntermediate,
+    /// The certificate is not valid for the name of the target
+    NameMismatch,
+    Expired,
+    ExpiredIntermediate,
+    NotYetValid,
+    Revoked,
+    BadSignature,
+    /// The certificate may not be used for TLS server authentication
+    InvalidPurpose,
+    /// Any other reason, see the detail
+    Invalid,
+}
+
+impl ValidationStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ValidationStatus::Valid => "Valid",
+            ValidationStatus::UntrustedIssuer => "UntrustedIssuer",
+            ValidationStatus::SelfSigned => "SelfSigned",
+            ValidationStatus::MissingIntermediate => "MissingIntermediate",
+            ValidationStatus::NameMismatch => "NameMismatch",
+            ValidationStatus::Expired => "Expired",
+            ValidationStatus::ExpiredIntermediate => "ExpiredIntermediate",
+            ValidationStatus::NotYetValid => "NotYetValid",
+            ValidationStatus::Revoked => "Revoked",
+            ValidationStatus::BadSignature => "BadSignature",
+            ValidationStatus::InvalidPurpose => "InvalidPurpose",
+            ValidationStatus::Invalid => "Invalid",
+        }
+    }
+}
+
+/// Outcome of the validation of the presented chain against the trust anchors
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub status: ValidationStatus,
+    /// Error reported by the verifier if the chain is not valid
+    pub detail: Option<String>,
+}
+
+impl Validation {
+    pub fn is_valid(&self) -> bool {
+        self.status == ValidationStatus::Valid
+    }
+}
+
+/// Whether the certificates were presented in the order required by RFC 5246, each one issued by the next
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ChainOrder {
+    Ordered,
+    /// All issuers are present, but not each directly after the certificate it issued
+    Misordered,
+    /// The issuer of a certificate other than the last one is missing
+    Incomplete,
+}
+
+impl ChainOrder {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ChainOrder::Ordered => "Ordered",
+            ChainOrder::Misordered => "Misordered",
+            ChainOrder::Incomplete => "Incomplete",
+        }
+    }
+}
+
+/// Checks that the issuer of each certificate of the presented chain matches the subject of the next one.
+/// Whether the last certificate leads to a trust anchor is up to [validate_chain].
+pub fn check_chain_order(chain: &[CertificateDer]) -> ChainOrder {
+    let names: Vec<(Vec<u8>, Vec<u8>)> = chain.iter()
+        .filter_map(|cert| X509Certificate::from_der(cert.as_ref()).ok())
+        .map(|(_, cert)| (cert.subject().as_raw().to_vec(), cert.issuer().as_raw().to_vec()))
+        .collect();
+    let mut order = ChainOrder::Ordered;
+    for pair in names.windows(2) {
+        let (_, issuer) = &pair[0];
+        let (next_subject, _) = &pair[1];
+        if issuer != next_subject {
+            if names.iter().any(|(subject, _)| subject == issuer) {
+                order = ChainOrder::Misordered;
+            } else {
+                return ChainOrder::Incomplete;
+            }
+        }
+    }
+    order
+}
+
+fn is_self_signed(cert: &CertificateDer) -> bool {
+    X509Certificate::from_der(cert.as_ref())
+        .map(|(_, cert)| cert.subject().as_raw() == cert.issuer().as_raw())
+        .unwrap_or(false)
+}
+
+fn is_expired(cert: &CertificateDer, now: UnixTime) -> bool {
+    X509Certificate::from_der(cert.as_ref())
+        .map(|(_, cert)| (cert.validity().not_after.timestamp() as u64) < now.as_secs())
+        .unwrap_or(false)
+}
+
+fn classify(error: &Error, end_entity: &CertificateDer, intermediates: &[CertificateDer], now: UnixTime) -> ValidationStatus {
+    match error {
+        Error::InvalidCertificate(e) => match e {
+            CertificateError::UnknownIssuer => {
+                if is_self_signed(end_entity) {
+                    ValidationStatus::SelfSigned
+                } else if intermediates.is_empty() {
+                    ValidationStatus::MissingIntermediate
+                } else {
+                    ValidationStatus::UntrustedIssuer
+                }
+            }
+            CertificateError::Expired | CertificateError::ExpiredContext { .. } => {
+                if is_expired(end_entity, now) {
+                    ValidationStatus::Expired
+                } else {
+                    ValidationStatus::ExpiredIntermediate
+                }
+            }
+            CertificateError::NotValidYet | CertificateError::NotValidYetContext { .. } => ValidationStatus::NotYetValid,
+            CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. } => ValidationStatus::NameMismatch,
+            CertificateError::Revoked => ValidationStatus::Revoked,
+            CertificateError::BadSignature => ValidationStatus::BadSignature,
+            CertificateError::InvalidPurpose | CertificateError::InvalidPurposeContext { .. } => ValidationStatus::InvalidPurpose,
+            _ => ValidationStatus::Invalid,
+        },
+        _ => ValidationStatus::Invalid,
+    }
+}
+
+/// Validates the chain as presented by the server (end entity first) for `server_name`
+pub fn validate_chain(verifier: &WebPkiServerVerifier, chain: &[CertificateDer], server_name: &ServerName, now: UnixTime) -> Validation {
+    let Some((end_entity, intermediates)) = chain.split_first() else {
+        return Validation {
+            status: ValidationStatus::Invalid,
+            detail: Some("no certificate presented".to_owned()),
+        };
+    };
+    match verifier.verify_server_cert(end_entity, intermediates, server_name, &[], now) {
+        Ok(_) => Validation {
+            status: ValidationStatus::Valid,
+            detail: None,
+        },
+        Err(e) => Validation {
+            status: classify(&e, end_entity, intermediates, now),
+            detail: Some(e.to_string()),
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::RootCertStore;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    const ROOT: &[u8] = include_bytes!("../testdata/pki/root.crt");
+    const INTERMEDIATE: &[u8] = include_bytes!("../testdata/pki/intermediate.crt");
+    const LOCALHOST: &[u8] = include_bytes!("../testdata/pki/localhost.crt");
+    const SELF_SIGNED: &[u8] = include_bytes!("../testdata/pki/selfsigned.crt");
+    const GITEA_CERT: &[u8] = include_bytes!("../testdata/gitea.tschirky.ch.crt");
+
+    fn pem(data: &[u8]) -> CertificateDer<'static> {
+        CertificateDer::from_pem_slice(data).unwrap()
+    }
+
+    fn verifier(root: Option<&[u8]>) -> Arc<WebPkiServerVerifier> {
+        let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.into() };
+        if let Some(root) = root {
+            roots.add(pem(root)).unwrap();
+        }
+        WebPkiServerVerifier::builder(Arc::new(roots)).build().unwrap()
+    }
+
+    fn name(name: &str) -> ServerName<'static> {
+        ServerName::try_from(name.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_valid_chain() {
+        let chain = [pem(LOCALHOST), pem(INTERMEDIATE)];
+        let validation = validate_chain(&verifier(Some(ROOT)), &chain, &name("localhost"), UnixTime::now());
+        assert_eq!(validation, Validation { status: ValidationStatus::Valid, detail: None });
+    }
+
+    #[test]
+    fn test_name_mismatch() {
+        let chain = [pem(LOCALHOST), pem(INTERMEDIATE)];
+        let validation = validate_chain(&verifier(Some(ROOT)), &chain, &name("www.tschirky.ch"), UnixTime::now());
+        assert_eq!(validation.status, ValidationStatus::NameMismatch);
+    }
+
+    #[test]
+    fn test_untrusted_issuer() {
+        let chain = [pem(LOCALHOST), pem(INTERMEDIATE)];
+        let validation = validate_chain(&verifier(None), &chain, &name("localhost"), UnixTime::now());
+        assert_eq!(validation.status, ValidationStatus::UntrustedIssuer);
+        assert!(validation.detail.is_some());
+    }
+
+    #[test]
+    fn test_missing_intermediate() {
+        let chain = [pem(LOCALHOST)];
+        let validation = validate_chain(&verifier(Some(ROOT)), &chain, &name("localhost"), UnixTime::now());
+        assert_eq!(validation.status, ValidationStatus::MissingIntermediate);
+
+        // Let's Encrypt R11 is not sent along
+        let chain = [CertificateDer::from(GITEA_CERT)];
+        let valid_time = UnixTime::since_unix_epoch(Duration::from_secs(1739161800));
+        let validation = validate_chain(&verifier(None), &chain, &name("gitea.tschirky.ch"), valid_time);
+        assert_eq!(validation.status, ValidationStatus::MissingIntermediate);
+    }
+
+    #[test]
+    fn test_self_signed() {
+        let chain = [pem(SELF_SIGNED)];
+        let validation = validate_chain(&verifier(None), &chain, &name("selfsigned.test.cert-monitor.local"), UnixTime::now());
+        assert_eq!(validation.status, ValidationStatus::SelfSigned);
+    }
+
+    #[test]
+    fn test_expired() {
+        let chain = [pem(LOCALHOST), pem(INTERMEDIATE)];
+        let far_future = UnixTime::since_unix_epoch(Duration::from_secs(4_900_000_000));
+        let validation = validate_chain(&verifier(Some(ROOT)), &chain, &name("localhost"), far_future);
+        assert_eq!(validation.status, ValidationStatus::Expired);
+    }
+
+    #[test]
+    fn test_chain_order() {
+        assert_eq!(check_chain_order(&[pem(LOCALHOST), pem(INTERMEDIATE)]), ChainOrder::Ordered);
+        assert_eq!(check_chain_order(&[pem(LOCALHOST), pem(INTERMEDIATE), pem(ROOT)]), ChainOrder::Ordered);
+        assert_eq!(check_chain_order(&[pem(LOCALHOST)]), ChainOrder::Ordered);
+        assert_eq!(check_chain_order(&[pem(LOCALHOST), pem(ROOT), pem(INTERMEDIATE)]), ChainOrder::Misordered);
+        assert_eq!(check_chain_order(&[pem(LOCALHOST), pem(ROOT)]), ChainOrder::Incomplete);
+        assert_eq!(check_chain_order(&[]), ChainOrder::Ordered);
+    }
+
+    #[test]
+    fn test_empty_chain() {
+        let validation = validate_chain(&verifier(None), &[], &name("localhost"), UnixTime::now());
+        assert_eq!(validation.status, ValidationStatus::Invalid);
+    }
+}
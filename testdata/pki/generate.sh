@@ -0,0 +1,76 @@
+#!/bin/bash
+# Generates the test PKI used by the unit tests. Keys are PKCS#8 encoded EC P-256 keys.
+set -e
+cd "$(dirname "$0")"
+CONF="$(openssl version -d | cut -d'"' -f2)/openssl.cnf"
+
+key() {
+    openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out "$1.key"
+}
+
+key root
+openssl req -x509 -new -key root.key -subj "/O=Cert Monitor Test/CN=Cert Monitor Test Root CA" -days 36500 -sha256 \
+    -extensions root -config <(cat "$CONF" ext.cnf) -out root.crt
+
+key intermediate
+openssl req -new -key intermediate.key -subj "/O=Cert Monitor Test/CN=Cert Monitor Test Intermediate CA" | \
+    openssl x509 -req -CA root.crt -CAkey root.key -CAcreateserial -days 36000 -sha256 \
+    -extfile ext.cnf -extensions intermediate -out intermediate.crt
+
+key localhost
+openssl req -new -key localhost.key -subj "/CN=localhost" | \
+    openssl x509 -req -CA intermediate.crt -CAkey intermediate.key -CAcreateserial -days 35000 -sha256 \
+    -extfile ext.cnf -extensions leaf -out localhost.crt
+
+key legacy
+openssl req -new -key legacy.key -subj "/CN=Legacy.Test.Cert-Monitor.local" | \
+    openssl x509 -req -CA intermediate.crt -CAkey intermediate.key -CAcreateserial -days 35000 -sha256 \
+    -extfile ext.cnf -extensions legacy -out legacy.crt
+
+key selfsigned
+openssl req -x509 -new -key selfsigned.key -subj "/CN=selfsigned.test.cert-monitor.local" -days 36500 -sha256 \
+    -extensions selfsigned -config <(cat "$CONF" ext.cnf) -out selfsigned.crt
+
+# EC P-384 key and all extensions of the certificate profile
+openssl ecparam -name secp384r1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out profile.key
+openssl req -new -key profile.key -subj "/C=CH/O=Cert Monitor Test/CN=profile.test.cert-monitor.local" | \
+    openssl x509 -req -CA intermediate.crt -CAkey intermediate.key -CAcreateserial -days 35000 -sha384 \
+    -extfile ext.cnf -extensions profile -out profile.crt
+
+# RSA 1024 key, SHA-1 signature and longer valid than 398 days, for the lint rules
+mkdir -p lint
+openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:1024 -out lint/weak.key
+openssl req -new -key lint/weak.key -subj "/CN=weak.test.cert-monitor.local" | \
+    openssl x509 -req -CA intermediate.crt -CAkey intermediate.key -CAcreateserial -days 825 -sha1 \
+    -extfile ext.cnf -extensions weak -out lint/weak.crt
+
+mkdir -p client
+key client/client
+openssl req -new -key client/client.key -subj "/O=Cert Monitor Test/CN=monitor" | \
+    openssl x509 -req -CA intermediate.crt -CAkey intermediate.key -CAcreateserial -days 35000 -sha256 \
+    -extfile ext.cnf -extensions client -out client/client.crt
+openssl pkcs12 -export -in client/client.crt -inkey client/client.key -certfile intermediate.crt \
+    -passout pass:changeit -out client/client.p12
+
+# certificates as found on disk, for the inspection of local files
+mkdir -p files
+openssl x509 -in localhost.crt -outform der -out files/localhost.der
+cat localhost.crt intermediate.crt > files/fullchain.pem
+cat root.crt selfsigned.crt > files/bundle.pem
+cp localhost.key files/localhost.key.pem
+
+# Java keystores with the localhost key and chain as `server` and the root as trusted certificate `root`
+mkdir -p keystores
+echo changeit > keystores/password.txt
+openssl pkcs12 -export -in localhost.crt -inkey localhost.key -certfile intermediate.crt -name server \
+    -passout pass:changeit -out keystores/server.p12
+keytool -importcert -noprompt -alias root -file root.crt -keystore keystores/server.p12 -storetype PKCS12 \
+    -storepass changeit
+for type in jks jceks; do
+    keytool -importkeystore -noprompt -srckeystore keystores/server.p12 -srcstoretype PKCS12 -srcstorepass changeit \
+        -destkeystore keystores/server.$type -deststoretype $type -deststorepass changeit
+done
+keytool -genseckey -alias secret -keyalg AES -keysize 128 -keystore keystores/server.jceks -storetype JCEKS \
+    -storepass changeit -keypass changeit
+
+rm -f *.srl